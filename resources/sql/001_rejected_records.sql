@@ -0,0 +1,14 @@
+-- Quarantine table for payloads that could not be inserted into public.records.
+-- Only required if the `database` quarantine sink is configured.
+-- Payloads of the decode stage are not valid UTF-8 and stored base64 encoded.
+CREATE TABLE IF NOT EXISTS public.rejected_records
+(
+    id          BIGSERIAL PRIMARY KEY,
+    received_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    peer_addr   TEXT,
+    stage       TEXT                     NOT NULL,
+    error       TEXT                     NOT NULL,
+    payload     TEXT                     NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS rejected_records_received_at_idx ON public.rejected_records (received_at);
@@ -0,0 +1,5 @@
+-- Deduplication key for records resent by devices.
+-- Only required if `deduplication_parameters.database_upsert` is enabled.
+ALTER TABLE public.records ADD COLUMN IF NOT EXISTS dedup_key TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS records_sensor_id_dedup_key_idx ON public.records (sensor_id, dedup_key);
@@ -0,0 +1,5 @@
+-- Optional device metadata (firmware version, RSSI, location, battery state) sent with records.
+-- The column is always written by dblogd and must exist.
+ALTER TABLE public.records ADD COLUMN IF NOT EXISTS tags JSONB;
+
+CREATE INDEX IF NOT EXISTS records_tags_idx ON public.records USING GIN (tags);
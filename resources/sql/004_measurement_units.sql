@@ -0,0 +1,12 @@
+-- Original units of the measurements. The values themselves are stored in the canonical units
+-- degree celsius, percent relative humidity and pascal.
+ALTER TABLE public.temperature ADD COLUMN IF NOT EXISTS unit TEXT NOT NULL DEFAULT 'celsius';
+
+ALTER TABLE public.humidity ADD COLUMN IF NOT EXISTS unit TEXT NOT NULL DEFAULT 'percent';
+
+CREATE TABLE IF NOT EXISTS public.pressure
+(
+    record_id BIGINT           NOT NULL REFERENCES public.records (id),
+    pascal    DOUBLE PRECISION NOT NULL,
+    unit      TEXT             NOT NULL DEFAULT 'pa'
+);
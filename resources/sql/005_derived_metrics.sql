@@ -0,0 +1,10 @@
+-- Metrics derived from the temperature and relative humidity of a record.
+-- Only required if `derived_metrics_parameters` enables at least one metric.
+CREATE TABLE IF NOT EXISTS public.derived_metrics
+(
+    record_id                  BIGINT NOT NULL REFERENCES public.records (id),
+    dew_point_celsius          DOUBLE PRECISION,
+    absolute_humidity          DOUBLE PRECISION,
+    vapour_pressure_deficit_pa DOUBLE PRECISION,
+    heat_index_celsius         DOUBLE PRECISION
+);
@@ -0,0 +1,172 @@
+//! Module implementing the `check-config` subcommand.
+//!
+//! The configuration is validated as deeply as possible without starting the application:
+//!
+//! * The configuration is loaded from all its sources, unknown fields are reported as errors.
+//!
+//! * The logging configuration is validated without creating log files.
+//!
+//! * The quarantine file is checked and every certificate and key is loaded.
+//!
+//! * The ingest socket and the http endpoint are test-bound.
+//!
+//! * Optionally the database connection is established and the expected tables are verified.
+//!
+//! No files are created or written by the checks.
+//!
+use std::fs::OpenOptions;
+use std::io;
+use std::net::TcpListener;
+use std::path::Path;
+
+use crate::config::{Configuration, ConfigurationSource};
+use crate::quarantine::QuarantineParameters;
+use crate::socket::SocketParameters;
+use crate::{database, logging, socket};
+
+/// Struct collecting the diagnostics of the checks.
+struct Diagnostics
+{
+    /// The number of failed checks.
+    errors: usize,
+}
+
+impl Diagnostics {
+    /// Reports a successful check.
+    fn ok(&self, message: &str)
+    {
+        println!("ok:      {}", message);
+    }
+
+    /// Reports a problem that does not prevent the application from starting.
+    fn warning(&self, message: &str)
+    {
+        println!("warning: {}", message);
+    }
+
+    /// Reports a failed check.
+    fn error(&mut self, message: &str)
+    {
+        println!("error:   {}", message);
+        self.errors += 1;
+    }
+
+    /// Reports the result of a check.
+    fn check<T>(&mut self, description: &str, result: Result<T, String>) -> Option<T>
+    {
+        match result {
+            Ok(value) => {
+                self.ok(description);
+                Some(value)
+            }
+            Err(err) => {
+                self.error(format!("{}: {}", description, err).as_str());
+                None
+            }
+        }
+    }
+}
+
+/// Function to test-bind a socket.
+///
+/// An address that is already in use is only reported as a warning, as it is usually held by the
+/// running instance of the application.
+fn check_bind(diagnostics: &mut Diagnostics, description: &str, params: &SocketParameters)
+{
+    let address = format!("{}:{}", params.address, params.port);
+    match TcpListener::bind(address.as_str()) {
+        Ok(_) => diagnostics.ok(format!("{} can be bound to {}", description, address).as_str()),
+        Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+            diagnostics.warning(format!("{} address {} is already in use, possibly by a running dblogd", description, address).as_str());
+        }
+        Err(err) => diagnostics.error(format!("{} cannot be bound to {}: {}", description, address, err).as_str()),
+    };
+}
+
+/// Function to check that the quarantine file can be appended to, without creating it.
+fn check_quarantine(params: &Option<QuarantineParameters>) -> Result<(), String>
+{
+    let path = match params {
+        Some(QuarantineParameters::File { path }) => Path::new(path.as_str()),
+        _ => return Ok(()),
+    };
+    if path.exists() {
+        return match OpenOptions::new().append(true).open(path) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Could not open quarantine file \'{}\': \'{}\'", path.display(), err)),
+        };
+    }
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() && !parent.is_dir() => {
+            Err(format!("Folder of quarantine file \'{}\' does not exist", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Function to verify the database connection and schema.
+fn check_database(diagnostics: &mut Diagnostics, configuration: &Configuration)
+{
+    let params = &configuration.database_connection_parameters;
+    let description = format!("Database connection to {}:{}/{}", params.hostname, params.port, params.database);
+    let mut client = match diagnostics.check(description.as_str(), database::connect_database(params)) {
+        Some(client) => client,
+        None => return,
+    };
+
+    match database::verify_schema(&mut client, configuration) {
+        Ok(_) => diagnostics.ok("Database schema contains all expected tables"),
+        Err(err) => diagnostics.error(err.as_str()),
+    };
+}
+
+/// Function to validate the configuration.
+///
+/// The diagnostics are printed to stdout.
+///
+/// # Arguments
+///
+/// * `configuration_source` - The sources of the configuration.
+///
+/// * `connect_database` - Establish the database connection and verify the schema.
+///
+/// # Returns
+///
+/// `true` if all checks passed.
+///
+pub fn check_configuration(configuration_source: &ConfigurationSource, connect_database: bool) -> bool
+{
+    let mut diagnostics = Diagnostics { errors: 0 };
+
+    let (configuration, unknown_fields) = match diagnostics.check("Configuration can be loaded", configuration_source.load_with_unknown_fields()) {
+        Some(loaded) => loaded,
+        None => return false,
+    };
+    for unknown_field in unknown_fields {
+        diagnostics.error(format!("Unknown configuration field \'{}\'", unknown_field).as_str());
+    }
+
+    diagnostics.check("Logging configuration", logging::validate_log_config(&configuration));
+
+    diagnostics.check("Quarantine", check_quarantine(&configuration.quarantine_parameters));
+
+    diagnostics.check("Socket tls identity", socket::build_tls_acceptor(&configuration.socket_connection_parameters).map(|_| ()));
+    diagnostics.check("Database tls certificates", database::build_tls_connector(&configuration.database_connection_parameters).map(|_| ()));
+
+    check_bind(&mut diagnostics, "Ingest socket", &configuration.socket_connection_parameters.socket_params);
+    if let Some(http_parameters) = &configuration.http_parameters {
+        check_bind(&mut diagnostics, "Http endpoint", &http_parameters.socket_params);
+    }
+
+    if connect_database {
+        check_database(&mut diagnostics, &configuration);
+    }
+
+    if diagnostics.errors == 0 {
+        println!("Configuration is valid.");
+        true
+    } else {
+        println!("Configuration is invalid: {} error(s).", diagnostics.errors);
+        false
+    }
+}
@@ -0,0 +1,486 @@
+//! Module that contains the configuration of the application and the functions to load it.
+//!
+//! The configuration is assembled from the following layers, later layers take precedence:
+//!
+//! 1. The built-in defaults.
+//!
+//! 2. The optional YAML configuration file.
+//!
+//! 3. `DBLOGD_*` environment variables, nested fields are separated by `__`,
+//!    e.g. `DBLOGD_DATABASE__HOSTNAME`.
+//!
+//! 4. `--set` command line flags, nested fields are separated by `.`, e.g. `database.hostname=db`.
+//!
+//! The top level sections can be abbreviated by their name without the `_parameters` suffix,
+//! e.g. `database` for `database_connection_parameters`.
+//!
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time;
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+use crate::{database, deduplication, derived, http, logging, quarantine, secrets, socket};
+
+/// Prefix of the environment variables overriding the configuration.
+const ENV_PREFIX: &str = "DBLOGD_";
+/// Separator of nested fields in environment variables.
+const ENV_SEPARATOR: &str = "__";
+/// Separator of nested fields in command line overrides.
+const CLI_SEPARATOR: &str = ".";
+
+/// Abbreviations of the top level sections of the configuration.
+const SECTION_ALIASES: [(&str, &str); 6] = [
+    ("database", "database_connection_parameters"),
+    ("socket", "socket_connection_parameters"),
+    ("quarantine", "quarantine_parameters"),
+    ("deduplication", "deduplication_parameters"),
+    ("derived_metrics", "derived_metrics_parameters"),
+    ("http", "http_parameters"),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Struct representing the configuration of the application.
+pub struct Configuration {
+    /// Parameters for the database part ot the app.
+    #[serde(default)]
+    pub database_connection_parameters: database::DatabaseParameters,
+    /// Parameters for the socket part of the app.
+    #[serde(default)]
+    pub socket_connection_parameters: socket::TlsSocketParameters,
+    /// Logging folder location.
+    #[serde(default = "default_logging_folder")]
+    pub logging_folder: String,
+    /// Parameters of the log levels, format and rotation.
+    #[serde(default)]
+    pub logging: logging::LoggingParameters,
+    /// Native log4rs configuration file replacing the built-in logging configuration.
+    #[serde(default)]
+    pub logging_config_file: Option<String>,
+    /// Interval in seconds the logging configuration file is checked for changes, `0` disables the check.
+    #[serde(default = "default_logging_config_refresh_secs")]
+    pub logging_config_refresh_secs: u64,
+    /// Time in seconds to drain the queued records into the database on shutdown.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// Sink for payloads that cannot be inserted into the database.
+    #[serde(default)]
+    pub quarantine_parameters: Option<quarantine::QuarantineParameters>,
+    /// Parameters for the duplicate suppression.
+    #[serde(default)]
+    pub deduplication_parameters: Option<deduplication::DeduplicationParameters>,
+    /// Derived metrics computed for every record.
+    #[serde(default)]
+    pub derived_metrics_parameters: Option<derived::DerivedMetricsParameters>,
+    /// Parameters for the monitoring HTTP endpoint.
+    #[serde(default)]
+    pub http_parameters: Option<http::HttpParameters>,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration {
+            database_connection_parameters: database::DatabaseParameters::default(),
+            socket_connection_parameters: socket::TlsSocketParameters::default(),
+            logging_folder: default_logging_folder(),
+            logging: logging::LoggingParameters::default(),
+            logging_config_file: None,
+            logging_config_refresh_secs: default_logging_config_refresh_secs(),
+            shutdown_timeout_secs: default_shutdown_timeout_secs(),
+            quarantine_parameters: None,
+            deduplication_parameters: None,
+            derived_metrics_parameters: None,
+            http_parameters: None,
+        }
+    }
+}
+
+/// Default logging folder location.
+fn default_logging_folder() -> String
+{
+    String::from("/var/log/dblogd")
+}
+
+/// Default interval in seconds the logging configuration file is checked for changes.
+fn default_logging_config_refresh_secs() -> u64
+{
+    30
+}
+
+/// Default time in seconds to drain the queued records on shutdown.
+fn default_shutdown_timeout_secs() -> u64
+{
+    10
+}
+
+#[derive(Debug, Clone, Default)]
+/// Struct representing the sources the configuration is loaded from.
+pub struct ConfigurationSource
+{
+    /// The location of the configuration file, if any.
+    pub file: Option<PathBuf>,
+    /// The `key=value` overrides passed on the command line.
+    pub overrides: Vec<String>,
+}
+
+impl ConfigurationSource {
+    /// Loads the configuration from all layers, see the module documentation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a layer cannot be read, a `DBLOGD_*` environment variable is not valid
+    /// unicode or the result cannot be deserialized.
+    ///
+    pub fn load(&self) -> Result<Configuration, String>
+    {
+        self.load_with_unknown_fields().map(|(configuration, _)| configuration)
+    }
+
+    /// Loads the configuration from all layers and reports the fields that are not known.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok((Configuration, Vec<String>))` - The configuration and the paths of the ignored fields,
+    ///   e.g. `database_connection_parameters.hostnme`.
+    ///
+    /// * `Err(...)` - If a layer cannot be read, a `DBLOGD_*` environment variable is not valid
+    ///   unicode or the result cannot be deserialized.
+    ///
+    pub fn load_with_unknown_fields(&self) -> Result<(Configuration, Vec<String>), String>
+    {
+        let mut root = match serde_yaml::to_value(Configuration::default()) {
+            Ok(root) => root,
+            Err(err) => {
+                return Err(format!("Cannot serialize the default configuration: \'{}\'", err));
+            }
+        };
+
+        if let Some(file) = &self.file {
+            merge(&mut root, read_configuration_file(file)?);
+        }
+
+        for (key, value) in env::vars_os() {
+            if !key.to_string_lossy().starts_with(ENV_PREFIX) {
+                continue;
+            }
+            match (key.to_str(), value.to_str()) {
+                (Some(key), Some(value)) => {
+                    set_path(&mut root, override_path(&key[ENV_PREFIX.len()..], ENV_SEPARATOR).as_slice(), override_value(value));
+                }
+                _ => {
+                    return Err(format!("Environment variable '{}' is not valid unicode", key.to_string_lossy()));
+                }
+            };
+        }
+
+        for assignment in &self.overrides {
+            match assignment.split_once('=') {
+                Some((path, value)) => {
+                    set_path(&mut root, override_path(path, CLI_SEPARATOR).as_slice(), override_value(value));
+                }
+                None => {
+                    return Err(format!("Invalid configuration override \'{}\', expected key=value", assignment));
+                }
+            };
+        }
+
+        deserialize_configuration(root)
+    }
+}
+
+impl Configuration {
+    /// Returns the parameters for processing records before they are inserted.
+    pub fn processing_parameters(&self) -> database::ProcessingParameters
+    {
+        database::ProcessingParameters {
+            deduplication: self.deduplication_parameters.clone(),
+            derived_metrics: self.derived_metrics_parameters.clone(),
+            shutdown_timeout: time::Duration::from_secs(self.shutdown_timeout_secs),
+        }
+    }
+
+    /// Replaces all secret fields by their resolved values, see the `secrets` module.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a secret file cannot be read or an environment variable is not set.
+    ///
+    pub fn resolve_secrets(&mut self) -> Result<(), String>
+    {
+        let database = &mut self.database_connection_parameters;
+        database.password = secrets::resolve(Some(database.password.as_str()), database.password_file.as_deref())?
+            .unwrap_or_default();
+        database.password_file = None;
+
+        let socket = &mut self.socket_connection_parameters;
+        socket.pkcs12_file_password = secrets::resolve(socket.pkcs12_file_password.as_deref(), socket.pkcs12_file_password_file.as_deref())?;
+        socket.pkcs12_file_password_file = None;
+        socket.private_key_password = secrets::resolve(socket.private_key_password.as_deref(), socket.private_key_password_file.as_deref())?;
+        socket.private_key_password_file = None;
+
+        Ok(())
+    }
+}
+
+/// Function to read the configuration file.
+///
+/// # Arguments
+///
+/// * `config_file_path` - The path of the configuration file.
+///
+/// # Returns
+///
+/// * `Ok(Value)` - The YAML document of the file, an empty mapping if the file is empty.
+///
+/// * `Err(...)` - If the file cannot be opened, read or parsed.
+///
+fn read_configuration_file(config_file_path: &Path) -> Result<Value, String>
+{
+    let mut configuration_file = match File::open(config_file_path) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(format!("Cannot open the configuration file: \'{}\'", err));
+        }
+    };
+
+    let mut configuration_string = String::new();
+    match configuration_file.read_to_string(&mut configuration_string) {
+        Ok(_) => {}
+        Err(err) => {
+            return Err(format!("Cannot read the configuration from file: \'{}\'", err));
+        }
+    };
+
+    match serde_yaml::from_str::<Value>(configuration_string.as_str()) {
+        Ok(Value::Null) => Ok(Value::Mapping(Mapping::new())),
+        Ok(root) => Ok(root),
+        Err(err) => Err(format!("Cannot deserialize the configuration: \'{}\'", err)),
+    }
+}
+
+/// Function to deserialize the configuration and resolve its secrets.
+///
+/// The document is deserialized from its text form, so plain scalars of overrides like `5432`
+/// can be used for both number and string fields.
+///
+/// # Arguments
+///
+/// * `root` - The merged YAML document of all layers.
+///
+/// # Returns
+///
+/// * `Ok((Configuration, Vec<String>))` - The configuration and the paths of the ignored fields.
+///
+/// * `Err(...)` - If the document cannot be deserialized, a setting is invalid or a secret cannot
+///   be resolved.
+///
+fn deserialize_configuration(root: Value) -> Result<(Configuration, Vec<String>), String>
+{
+    let configuration_string = match serde_yaml::to_string(&root) {
+        Ok(configuration_string) => configuration_string,
+        Err(err) => {
+            return Err(format!("Cannot serialize the merged configuration: \'{}\'", err));
+        }
+    };
+
+    let mut unknown_fields = vec![];
+    let deserializer = serde_yaml::Deserializer::from_str(configuration_string.as_str());
+    let mut configuration: Configuration = match serde_ignored::deserialize(deserializer, |path| unknown_fields.push(path.to_string())) {
+        Ok(res) => res,
+        Err(err) => {
+            return Err(format!("Cannot deserialize the configuration: \'{}\'", err));
+        }
+    };
+
+    configuration.socket_connection_parameters.validate()?;
+    configuration.resolve_secrets()?;
+    Ok((configuration, unknown_fields))
+}
+
+/// Function to split the key of an override into the path of the field.
+///
+/// Keys are case insensitive and the first segment may be a section abbreviation.
+///
+/// # Arguments
+///
+/// * `key` - The key of the override without prefix, e.g. `DATABASE__HOSTNAME`.
+///
+/// * `separator` - The separator of nested fields.
+///
+/// # Returns
+///
+/// The lower case names of the nested fields, e.g. `["database_connection_parameters", "hostname"]`.
+///
+fn override_path(key: &str, separator: &str) -> Vec<String>
+{
+    let mut path: Vec<String> = key.split(separator).map(|segment| segment.to_lowercase()).collect();
+    if let Some(section) = path.first_mut() {
+        if let Some((_, name)) = SECTION_ALIASES.iter().find(|(alias, _)| alias == section) {
+            *section = String::from(*name);
+        }
+    }
+    path
+}
+
+/// Function to parse the value of an override.
+///
+/// Numbers, booleans, sequences and mappings are parsed as YAML, everything else is kept as
+/// string, so values like `0123` are not altered. A YAML null, e.g. `null` or `~`, unsets an
+/// optional field.
+///
+/// # Arguments
+///
+/// * `value` - The value of the override.
+///
+/// # Returns
+///
+/// The YAML value of the override.
+///
+fn override_value(value: &str) -> Value
+{
+    match serde_yaml::from_str::<Value>(value) {
+        Ok(parsed @ Value::Sequence(_)) | Ok(parsed @ Value::Mapping(_)) => parsed,
+        Ok(Value::Null) if !value.trim().is_empty() => Value::Null,
+        Ok(parsed @ Value::Number(_)) | Ok(parsed @ Value::Bool(_)) => {
+            let canonical = serde_yaml::to_string(&parsed).unwrap_or_default();
+            if canonical.trim_start_matches("---").trim() == value.trim() {
+                parsed
+            } else {
+                Value::String(String::from(value))
+            }
+        }
+        _ => Value::String(String::from(value)),
+    }
+}
+
+/// Function to merge a YAML document into another.
+///
+/// Mappings are merged recursively, all other values of `overlay` replace the ones in `base`.
+///
+/// # Arguments
+///
+/// * `base` - The document that is modified.
+///
+/// * `overlay` - The document taking precedence.
+///
+fn merge(base: &mut Value, overlay: Value)
+{
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                };
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Function to set a nested field of a YAML document, creating missing mappings on the way.
+///
+/// # Arguments
+///
+/// * `root` - The YAML document.
+///
+/// * `path` - The names of the nested fields.
+///
+/// * `value` - The value of the field.
+///
+fn set_path(root: &mut Value, path: &[String], value: Value)
+{
+    let (name, rest) = match path.split_first() {
+        Some(split) => split,
+        None => {
+            *root = value;
+            return;
+        }
+    };
+
+    if !root.is_mapping() {
+        *root = Value::Mapping(Mapping::new());
+    }
+    if let Value::Mapping(mapping) = root {
+        let key = Value::String(name.clone());
+        if !mapping.contains_key(&key) {
+            mapping.insert(key.clone(), Value::Null);
+        }
+        if let Some(child) = mapping.get_mut(&key) {
+            set_path(child, rest, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn yaml(document: &str) -> Value
+    {
+        serde_yaml::from_str(document).unwrap()
+    }
+
+    #[test]
+    fn override_path_resolves_aliases_and_case()
+    {
+        assert_eq!(override_path("DATABASE__HOSTNAME", ENV_SEPARATOR), vec!["database_connection_parameters", "hostname"]);
+        assert_eq!(override_path("socket.socket_params.port", CLI_SEPARATOR), vec!["socket_connection_parameters", "socket_params", "port"]);
+        assert_eq!(override_path("LOGGING_FOLDER", ENV_SEPARATOR), vec!["logging_folder"]);
+        assert_eq!(override_path("http_parameters.database", CLI_SEPARATOR), vec!["http_parameters", "database"]);
+    }
+
+    #[test]
+    fn override_value_parses_yaml_scalars_and_collections()
+    {
+        assert_eq!(override_value("5432"), Value::Number(5432.into()));
+        assert_eq!(override_value("true"), Value::Bool(true));
+        assert_eq!(override_value("[a, b]"), yaml("[a, b]"));
+        assert_eq!(override_value("{a: 1}"), yaml("{a: 1}"));
+        assert_eq!(override_value("postgres.test"), Value::String(String::from("postgres.test")));
+    }
+
+    #[test]
+    fn override_value_keeps_non_canonical_values_as_strings()
+    {
+        assert_eq!(override_value("0123"), Value::String(String::from("0123")));
+        assert_eq!(override_value("1.50"), Value::String(String::from("1.50")));
+        assert_eq!(override_value("yes"), Value::String(String::from("yes")));
+    }
+
+    #[test]
+    fn override_value_unsets_optional_fields()
+    {
+        assert_eq!(override_value("null"), Value::Null);
+        assert_eq!(override_value("~"), Value::Null);
+        assert_eq!(override_value(""), Value::String(String::new()));
+
+        let mut root = yaml("{socket_params: {address: 0.0.0.0, port: 31454}, pkcs12_identity_file: /etc/dblogd/identity.p12}");
+        set_path(&mut root, &[String::from("pkcs12_identity_file")], override_value("null"));
+        let params: socket::TlsSocketParameters = serde_yaml::from_value(root).unwrap();
+        assert_eq!(params.pkcs12_identity_file, None);
+    }
+
+    #[test]
+    fn merge_replaces_values_and_merges_mappings()
+    {
+        let mut base = yaml("{a: {b: 1, c: 2}, d: [1, 2], e: x}");
+        merge(&mut base, yaml("{a: {c: 3, f: 4}, d: [3], g: y}"));
+        assert_eq!(base, yaml("{a: {b: 1, c: 3, f: 4}, d: [3], e: x, g: y}"));
+    }
+
+    #[test]
+    fn set_path_creates_missing_mappings()
+    {
+        let mut root = yaml("{a: {b: 1}, c: 2}");
+        set_path(&mut root, &[String::from("a"), String::from("d"), String::from("e")], Value::Bool(true));
+        set_path(&mut root, &[String::from("c"), String::from("f")], Value::Number(3.into()));
+        assert_eq!(root, yaml("{a: {b: 1, d: {e: true}}, c: {f: 3}}"));
+    }
+}
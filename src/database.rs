@@ -1,21 +1,46 @@
 //! Module for connecting to a postgres database and storing the records received from a socket in
 //! the database.
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::Receiver;
-use std::time;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::{thread, time};
 
 use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
-use postgres::Client;
+use postgres::{Client, Transaction};
 use postgres_openssl::MakeTlsConnector;
 use serde::{Deserialize, Serialize};
 
-use crate::record::TemperatureRecord;
+use crate::config::Configuration;
+use crate::deduplication::{self, DeduplicationParameters, Deduplicator};
+use crate::derived::{self, DerivedMetrics, DerivedMetricsParameters};
+use crate::metrics::Metrics;
+use crate::quarantine::{Quarantine, QuarantineParameters, RejectedRecord, RejectionStage};
+use crate::record::{ReceivedRecord, TemperatureRecord};
+use crate::shared::read_shared;
+
+/// The location of the schema migrations installed by the package.
+const MIGRATIONS_FOLDER: &str = "/usr/share/dblogd/sql";
+
+/// The columns the application always writes to, as `(table, column)` pairs.
+const REQUIRED_COLUMNS: [(&str, &str); 10] = [
+    ("sensors", "id"),
+    ("sensors", "name"),
+    ("records", "id"),
+    ("records", "timestamp"),
+    ("records", "sensor_id"),
+    ("records", "tags"),
+    ("temperature", "unit"),
+    ("humidity", "unit"),
+    ("pressure", "pascal"),
+    ("pressure", "unit"),
+];
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 /// Struct modeling the parameters required for a database connection.
 ///
 /// This includes SSL/TLS encryption.
+/// Missing parameters default to a local database and the certificate locations of the package.
 pub struct DatabaseParameters
 {
     /// The hostname of the database server.
@@ -26,6 +51,8 @@ pub struct DatabaseParameters
     pub username: String,
     /// The password to connect with.
     pub password: String,
+    /// The file to read the password from instead, see the `secrets` module.
+    pub password_file: Option<String>,
     /// The database to open on the server.
     pub database: String,
     /// The path to the server certificate for TLS encryption.
@@ -36,27 +63,54 @@ pub struct DatabaseParameters
     pub client_key_path: String,
 }
 
-/// Function to insert a temperature record into the database.
+impl Default for DatabaseParameters {
+    fn default() -> Self {
+        DatabaseParameters {
+            hostname: String::from("localhost"),
+            port: 5432,
+            username: String::from("dblogd"),
+            password: String::new(),
+            password_file: None,
+            database: String::from("dblogd"),
+            server_ca_path: String::from("/etc/dblogd/certs/db/server-ca.full.pem"),
+            client_cert_path: String::from("/etc/dblogd/certs/db/client-cert.pem"),
+            client_key_path: String::from("/etc/dblogd/certs/db/client-key.pem"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Struct bundling the parameters for processing records before they are inserted.
+pub struct ProcessingParameters
+{
+    /// Parameters for the duplicate suppression or `None` if disabled.
+    pub deduplication: Option<DeduplicationParameters>,
+    /// The derived metrics to compute or `None` if disabled.
+    pub derived_metrics: Option<DerivedMetricsParameters>,
+    /// Time to drain the queued records into the database on shutdown.
+    pub shutdown_timeout: time::Duration,
+}
+
+/// Function to query the id of a known sensor.
 ///
 /// # Arguments
 ///
 /// * `database_client` - Database connection to execute the queries on.
 ///
-/// * `temperature_record` - The record to add to the database.
+/// * `sensor_name` - The name of the sensor to look up.
 ///
 /// # Returns
 ///
-/// * `Ok(())` - On success.
+/// * `Ok(i64)` - The id of the sensor on success.
 ///
-/// * `Err(...)` - If a single operation fails.
-///     Failing operations can be if a record cannot be inserted into the database.
-///     The sensor with this name does not exist.
+/// * `Err(...)` - If the sensor with this name does not exist or is not unique.
 ///
-fn insert_temperature_record(database_client: &mut Client, temperature_record: TemperatureRecord) -> Result<(), String>
+fn query_sensor_id(database_client: &mut Client, sensor_name: &str) -> Result<i64, String>
 {
-    let sensor_name_query_results = match database_client.query("SELECT sen.id FROM public.sensors sen WHERE sen.name = $1", &[&temperature_record.sensor_name]) {
+    let sensor_name_query_results = match database_client.query("SELECT sen.id FROM public.sensors sen WHERE sen.name = $1", &[&sensor_name]) {
         Ok(rows) => rows,
         Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
             log::warn!(target: "dblogd::db", "Could not find sensor name in known sensors: \'{}\'", err);
             return Err(String::from("Could not find sensor nama in known sensors!"));
         }
@@ -67,149 +121,684 @@ fn insert_temperature_record(database_client: &mut Client, temperature_record: T
         return Err(String::from("Found non unique sensor name, please ensure database consistency!"));
     };
 
-    let sensor_name_id: i64 = sensor_name_query_results.get(0).unwrap().get("id");
+    Ok(sensor_name_query_results.first().unwrap().get("id"))
+}
+
+/// Function to insert the derived metrics of a record into the database.
+///
+/// # Arguments
+///
+/// * `transaction` - Transaction of the record to execute the queries in.
+///
+/// * `record_id` - The id of the record the metrics were derived from.
+///
+/// * `derived_metrics` - The metrics to add to the database.
+///
+/// # Returns
+///
+/// * `Ok(())` - On success.
+///
+/// * `Err(...)` - If the metrics cannot be inserted into the database.
+///
+fn insert_derived_metrics(transaction: &mut Transaction, record_id: i64, derived_metrics: &DerivedMetrics) -> Result<(), String>
+{
+    match transaction.execute("INSERT INTO public.derived_metrics \
+                                   (record_id, dew_point_celsius, absolute_humidity, vapour_pressure_deficit_pa, heat_index_celsius) \
+                                   VALUES ($1, $2, $3, $4, $5)",
+                                  &[&record_id,
+                                      &derived_metrics.dew_point_celsius,
+                                      &derived_metrics.absolute_humidity,
+                                      &derived_metrics.vapour_pressure_deficit_pa,
+                                      &derived_metrics.heat_index_celsius]) {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
+            log::warn!(target: "dblogd::db", "Could not insert derived metrics into database: \'{}\'", err);
+            Err(String::from("Could not insert derived metrics into database"))
+        }
+    }
+}
+
+/// Function to insert a temperature record into the database.
+///
+/// The record and all its values are inserted in a single transaction, so a failed insert can be
+/// retried without the deduplication key of the record already being stored.
+///
+/// # Arguments
+///
+/// * `database_client` - Database connection to execute the queries on.
+///
+/// * `sensor_name_id` - The id of the sensor that recorded the record.
+///
+/// * `temperature_record` - The record to add to the database.
+///
+/// * `dedup_key` - The deduplication key of the record. If set, records with the same key for the
+///   same sensor are skipped by the unique constraint on `public.records`.
+///
+/// * `derived_metrics` - The metrics derived from the record, if any are enabled.
+///
+/// # Returns
+///
+/// * `Ok(true)` - On success.
+///
+/// * `Ok(false)` - If the record was skipped as a duplicate.
+///
+/// * `Err(...)` - If a single operation fails.
+///   Failing operations can be if a record cannot be inserted into the database.
+///
+fn insert_temperature_record(database_client: &mut Client,
+                             sensor_name_id: i64,
+                             temperature_record: &TemperatureRecord,
+                             dedup_key: Option<&str>,
+                             derived_metrics: Option<&DerivedMetrics>) -> Result<bool, String>
+{
+    let mut transaction = match database_client.transaction() {
+        Ok(transaction) => transaction,
+        Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
+            log::warn!(target: "dblogd::db", "Could not start database transaction: \'{}\'", err);
+            return Err(String::from("Could not start database transaction"));
+        }
+    };
 
-    let new_records_result = match database_client.query("INSERT INTO public.records (timestamp, sensor_id) VALUES ($1, $2) RETURNING id",
-                                                         &[&temperature_record.timestamp, &sensor_name_id]) {
+    let tags = temperature_record.tags.clone().map(serde_json::Value::Object);
+    let new_records_query = match dedup_key {
+        Some(key) => transaction.query("INSERT INTO public.records (timestamp, sensor_id, tags, dedup_key) VALUES ($1, $2, $3, $4) \
+                                            ON CONFLICT (sensor_id, dedup_key) DO NOTHING RETURNING id",
+                                           &[&temperature_record.timestamp, &sensor_name_id, &tags, &key]),
+        None => transaction.query("INSERT INTO public.records (timestamp, sensor_id, tags) VALUES ($1, $2, $3) RETURNING id",
+                                      &[&temperature_record.timestamp, &sensor_name_id, &tags]),
+    };
+    let new_records_result = match new_records_query {
         Ok(rows) => rows,
         Err(err) => {
-            log::warn!(target: "dblog::db", "Could not insert record into database: \'{}\'", err);
+            let _error = log_mdc::insert_scoped("error", err.to_string());
+            log::warn!(target: "dblogd::db", "Could not insert record into database: \'{}\'", err);
             return Err(String::from("Could not insert record into database"));
         }
     };
 
+    if dedup_key.is_some() && new_records_result.is_empty() {
+        return Ok(false);
+    };
+
     if new_records_result.len() != 1 {
         log::warn!(target: "dblogd::db", "Found non unique record id result, please ensure database consistency!");
         return Err(String::from("Found non unique record id result, please ensure database consistency!"));
     };
 
-    let new_record_id: i64 = new_records_result.get(0).unwrap().get("id");
+    let new_record_id: i64 = new_records_result.first().unwrap().get("id");
 
-    match database_client.execute("INSERT INTO public.temperature (record_id, celsius) VALUES ($1, $2)",
-                                  &[&new_record_id, &temperature_record.celsius]) {
+    match transaction.execute("INSERT INTO public.temperature (record_id, celsius, unit) VALUES ($1, $2, $3)",
+                                  &[&new_record_id, &temperature_record.temperature_celsius(), &temperature_record.temperature_unit.as_str()]) {
         Ok(_) => {}
         Err(err) => {
-            log::warn!(target: "dblog::db", "Could not insert celsius value into database: \'{}\'", err);
+            let _error = log_mdc::insert_scoped("error", err.to_string());
+            log::warn!(target: "dblogd::db", "Could not insert celsius value into database: \'{}\'", err);
             return Err(String::from("Could not insert celsius value into database"));
         }
     };
 
-    match database_client.execute("INSERT INTO public.humidity (record_id, humidity) VALUES ($1, $2)",
-                                  &[&new_record_id, &temperature_record.humidity]) {
+    match transaction.execute("INSERT INTO public.humidity (record_id, humidity, unit) VALUES ($1, $2, $3)",
+                                  &[&new_record_id, &temperature_record.humidity_percent(), &temperature_record.humidity_unit.as_str()]) {
         Ok(_) => {}
         Err(err) => {
-            log::warn!(target: "dblog::db", "Could not insert celsius value into database: \'{}\'", err);
-            return Err(String::from("Could not insert celsius value into database"));
+            let _error = log_mdc::insert_scoped("error", err.to_string());
+            log::warn!(target: "dblogd::db", "Could not insert humidity value into database: \'{}\'", err);
+            return Err(String::from("Could not insert humidity value into database"));
+        }
+    };
+
+    if let Some(pascal) = temperature_record.pressure_pascal() {
+        match transaction.execute("INSERT INTO public.pressure (record_id, pascal, unit) VALUES ($1, $2, $3)",
+                                      &[&new_record_id, &pascal, &temperature_record.pressure_unit.as_str()]) {
+            Ok(_) => {}
+            Err(err) => {
+                let _error = log_mdc::insert_scoped("error", err.to_string());
+                log::warn!(target: "dblogd::db", "Could not insert pressure value into database: \'{}\'", err);
+                return Err(String::from("Could not insert pressure value into database"));
+            }
+        };
+    }
+
+    if let Some(derived_metrics) = derived_metrics {
+        insert_derived_metrics(&mut transaction, new_record_id, derived_metrics)?;
+    }
+
+    match transaction.commit() {
+        Ok(_) => {}
+        Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
+            log::warn!(target: "dblogd::db", "Could not commit record to database: \'{}\'", err);
+            return Err(String::from("Could not commit record to database"));
         }
     };
 
-    Ok(())
+    Ok(true)
 }
 
-/// Thread function for the database connection.
+/// Function to insert a received record into the database.
 ///
-/// This thread establishes a database connection and moves all data in the receive channel to the database.
+/// Duplicate records are skipped and records that cannot be inserted are passed to the quarantine.
 ///
-/// This function will run until the `thread_finish` parameter was set or the socket is closed by a error.
+/// # Arguments
+///
+/// * `database_client` - Database connection to execute the queries on.
+///
+/// * `received_record` - The received record to add to the database.
+///
+/// * `deduplicator` - The duplicate suppression, if enabled.
+///
+/// * `derived_metrics_parameters` - The derived metrics to compute, if enabled.
+///
+/// * `quarantine` - The quarantine for records that cannot be inserted.
+///
+/// * `metrics` - The metrics updated for the insert.
+///
+/// # Returns
+///
+/// * `Ok(())` - On success or if the record was skipped as a duplicate.
+///
+/// * `Err(...)` - If the sensor is unknown or the record cannot be inserted.
+///
+fn insert_received_record(database_client: &mut Client,
+                          received_record: ReceivedRecord,
+                          deduplicator: &mut Option<Deduplicator>,
+                          derived_metrics_parameters: &Option<DerivedMetricsParameters>,
+                          quarantine: &Quarantine,
+                          metrics: &Metrics) -> Result<(), String>
+{
+    let sensor_name = received_record.record.sensor_name.as_str();
+    let key = match deduplicator {
+        Some(deduplicator) => {
+            let key = deduplication::deduplication_key(&received_record.record);
+            if deduplicator.is_duplicate(sensor_name, key.as_str()) {
+                log::debug!(target: "dblogd::db", "Skipped duplicate record \'{}\' of sensor \'{}\'!", key, sensor_name);
+                metrics.records_duplicate.fetch_add(1, Ordering::Relaxed);
+                metrics.sensor_seen(sensor_name);
+                return Ok(());
+            }
+            Some(key)
+        }
+        None => None,
+    };
+    let dedup_key = match deduplicator {
+        Some(deduplicator) if deduplicator.database_upsert() => key.as_deref(),
+        _ => None,
+    };
+
+    let derived_metrics = match derived_metrics_parameters {
+        Some(params) if params.any_enabled() => Some(derived::compute(params,
+                                                                      received_record.record.temperature_celsius(),
+                                                                      received_record.record.humidity_percent())),
+        _ => None,
+    };
+
+    let insert_start = time::Instant::now();
+    let (stage, result) = match query_sensor_id(database_client, sensor_name) {
+        Ok(sensor_name_id) => (RejectionStage::Insert, insert_temperature_record(database_client,
+                                                                                 sensor_name_id,
+                                                                                 &received_record.record,
+                                                                                 dedup_key,
+                                                                                 derived_metrics.as_ref())),
+        Err(err) => (RejectionStage::Sensor, Err(err)),
+    };
+
+    metrics.observe_insert_latency(insert_start.elapsed());
+
+    if let (Ok(_), Some(deduplicator), Some(key)) = (&result, deduplicator.as_mut(), &key) {
+        deduplicator.remember(sensor_name, key.as_str());
+    }
+
+    match result {
+        Ok(true) => {
+            metrics.records_inserted.fetch_add(1, Ordering::Relaxed);
+            metrics.sensor_seen(sensor_name);
+            Ok(())
+        }
+        Ok(false) => {
+            log::debug!(target: "dblogd::db", "Database skipped duplicate record of sensor \'{}\'!", sensor_name);
+            metrics.records_duplicate.fetch_add(1, Ordering::Relaxed);
+            metrics.sensor_seen(sensor_name);
+            Ok(())
+        }
+        Err(err) => {
+            metrics.insert_failed(stage.as_str());
+            quarantine.reject(RejectedRecord::new(
+                received_record.payload,
+                received_record.peer_addr,
+                received_record.received_at,
+                stage,
+                err.clone()));
+            Err(err)
+        }
+    }
+}
+
+/// Function to insert a rejected record into the `public.rejected_records` table.
 ///
 /// # Arguments
 ///
-/// * `rx` - The channel to receive the elements to insert from.
+/// * `database_client` - Database connection to execute the queries on.
 ///
-/// * `thread_finish` - Indicates that the thread should finish operation and should return.
+/// * `rejected_record` - The rejected record to add to the database.
 ///
-/// * `connection_parameters` - Parameters for the database connection.
+/// # Returns
 ///
-/// # Errors
+/// * `Ok(())` - On success.
 ///
-/// Errors occur when one of the following conditions is met:
+/// * `Err(...)` - If the rejected record cannot be inserted into the database.
 ///
-/// * The files for the TLS connection cannot be found.
+fn insert_rejected_record(database_client: &mut Client, rejected_record: &RejectedRecord) -> Result<(), String>
+{
+    match database_client.execute("INSERT INTO public.rejected_records (received_at, peer_addr, stage, error, payload) VALUES ($1, $2, $3, $4, $5)",
+                                  &[&rejected_record.received_at,
+                                      &rejected_record.peer_addr,
+                                      &rejected_record.stage.as_str(),
+                                      &rejected_record.error,
+                                      &rejected_record.payload]) {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
+            log::warn!(target: "dblogd::db", "Could not insert rejected record into database: \'{}\'", err);
+            Err(String::from("Could not insert rejected record into database"))
+        }
+    }
+}
+
+/// Function to create the TLS connector for the database connection.
 ///
-/// * The connection cannot be established.
+/// # Arguments
 ///
-/// * The the user is not authorized for the database.
+/// * `connection_parameters` - Parameters for the database connection.
 ///
-/// These errors will result in the method immediately exiting without raising a exception.
+/// # Returns
 ///
-pub fn database_thread(rx: Receiver<TemperatureRecord>, thread_finish: Arc<AtomicBool>, connection_parameters: DatabaseParameters)
+/// * `Ok(MakeTlsConnector)` - The connector on success.
+///
+/// * `Err(...)` - If the files for the TLS connection cannot be found or loaded.
+///
+pub fn build_tls_connector(connection_parameters: &DatabaseParameters) -> Result<MakeTlsConnector, String>
 {
     let mut ssl_connection_builder: openssl::ssl::SslConnectorBuilder = match SslConnector::builder(SslMethod::tls()) {
         Ok(builder) => builder,
         Err(err) => {
-            log::error!(target: "dblogd::db", "Could not create ssl connection builder: \'{}\'", err);
-            thread_finish.store(true, Ordering::SeqCst);
-            return;
+            return Err(format!("Could not create ssl connection builder: \'{}\'", err));
         }
     };
 
     ssl_connection_builder.set_verify(SslVerifyMode::NONE);
 
-    match ssl_connection_builder.set_ca_file(connection_parameters.server_ca_path) {
+    match ssl_connection_builder.set_ca_file(connection_parameters.server_ca_path.as_str()) {
         Ok(_) => {}
         Err(err) => {
-            log::error!(target: "dblogd::db", "Could not set ssl ca file: \'{}\'", err);
-            thread_finish.store(true, Ordering::SeqCst);
-            return;
+            return Err(format!("Could not set ssl ca file: \'{}\'", err));
         }
     };
 
-    match ssl_connection_builder.set_certificate_file(connection_parameters.client_cert_path, SslFiletype::PEM) {
+    match ssl_connection_builder.set_certificate_file(connection_parameters.client_cert_path.as_str(), SslFiletype::PEM) {
         Ok(_) => {}
         Err(err) => {
-            log::error!(target: "dblogd::db", "Could not set ssl client cert file: \'{}\'", err);
-            thread_finish.store(true, Ordering::SeqCst);
-            return;
+            return Err(format!("Could not set ssl client cert file: \'{}\'", err));
         }
     };
 
-    match ssl_connection_builder.set_private_key_file(connection_parameters.client_key_path, SslFiletype::PEM) {
+    match ssl_connection_builder.set_private_key_file(connection_parameters.client_key_path.as_str(), SslFiletype::PEM) {
         Ok(_) => {}
         Err(err) => {
-            log::error!(target: "dblogd::db", "Could not set ssl client key file: \'{}\'", err);
-            thread_finish.store(true, Ordering::SeqCst);
-            return;
+            return Err(format!("Could not set ssl client key file: \'{}\'", err));
         }
     };
 
-    let tls_connector = MakeTlsConnector::new(ssl_connection_builder.build());
+    Ok(MakeTlsConnector::new(ssl_connection_builder.build()))
+}
+
+/// Function to find the columns missing in the database.
+///
+/// # Arguments
+///
+/// * `database_client` - Database connection to execute the queries on.
+///
+/// * `columns` - The expected `(table, column)` pairs in the `public` schema.
+///
+/// # Returns
+///
+/// * `Ok(Vec<String>)` - The missing columns as `table.column`, empty if all exist.
+///
+/// * `Err(...)` - If the schema cannot be queried.
+///
+pub fn missing_columns(database_client: &mut Client, columns: &[(&str, &str)]) -> Result<Vec<String>, String>
+{
+    let mut missing = vec![];
+    for (table, column) in columns {
+        match database_client.query("SELECT 1 FROM information_schema.columns \
+                                     WHERE table_schema = 'public' AND table_name = $1 AND column_name = $2",
+                                    &[table, column]) {
+            Ok(rows) if rows.is_empty() => missing.push(format!("{}.{}", table, column)),
+            Ok(_) => {}
+            Err(err) => {
+                return Err(format!("Could not query the database schema: \'{}\'", err));
+            }
+        };
+    }
+    Ok(missing)
+}
+
+/// Function to list the columns written to with the configuration.
+///
+/// # Arguments
+///
+/// * `configuration` - The configuration of the application.
+///
+/// # Returns
+///
+/// The `(table, column)` pairs in the `public` schema, including the optional tables of the
+/// enabled quarantine sink, deduplication and derived metrics.
+///
+pub fn required_columns(configuration: &Configuration) -> Vec<(&'static str, &'static str)>
+{
+    let mut columns = REQUIRED_COLUMNS.to_vec();
+    if let Some(QuarantineParameters::Database) = configuration.quarantine_parameters {
+        columns.push(("rejected_records", "payload"));
+    }
+    if configuration.deduplication_parameters.as_ref().is_some_and(|params| params.database_upsert) {
+        columns.push(("records", "dedup_key"));
+    }
+    if configuration.derived_metrics_parameters.as_ref().is_some_and(|params| params.any_enabled()) {
+        columns.push(("derived_metrics", "record_id"));
+    }
+    columns
+}
+
+/// Function to check for the unique index required by the deduplication upsert.
+///
+/// # Arguments
+///
+/// * `database_client` - Database connection to execute the queries on.
+///
+/// # Returns
+///
+/// * `Ok(bool)` - Whether `public.records` has a unique index on exactly `(sensor_id, dedup_key)`.
+///
+/// * `Err(...)` - If the schema cannot be queried.
+///
+fn has_dedup_key_index(database_client: &mut Client) -> Result<bool, String>
+{
+    match database_client.query("SELECT 1 FROM pg_index idx \
+                                 JOIN pg_class tbl ON tbl.oid = idx.indrelid \
+                                 JOIN pg_namespace nsp ON nsp.oid = tbl.relnamespace \
+                                 WHERE nsp.nspname = 'public' AND tbl.relname = 'records' AND idx.indisunique \
+                                 AND (SELECT array_agg(att.attname::text ORDER BY att.attname) FROM pg_attribute att \
+                                      WHERE att.attrelid = tbl.oid AND att.attnum = ANY(idx.indkey)) = ARRAY['dedup_key', 'sensor_id'] \
+                                 AND idx.indnatts = 2",
+                                &[]) {
+        Ok(rows) => Ok(!rows.is_empty()),
+        Err(err) => Err(format!("Could not query the database schema: \'{}\'", err)),
+    }
+}
+
+/// Function to verify the database schema contains all columns written to with the configuration.
+///
+/// # Arguments
+///
+/// * `database_client` - Database connection to execute the queries on.
+///
+/// * `configuration` - The configuration of the application.
+///
+/// # Returns
+///
+/// * `Ok(())` - If all columns and the unique index of the deduplication upsert exist.
+///
+/// * `Err(...)` - If columns or the index are missing, naming them and the location of the migrations, or if
+///   the schema cannot be queried.
+///
+pub fn verify_schema(database_client: &mut Client, configuration: &Configuration) -> Result<(), String>
+{
+    let mut missing = missing_columns(database_client, required_columns(configuration).as_slice())?;
+    if configuration.deduplication_parameters.as_ref().is_some_and(|params| params.database_upsert)
+        && !has_dedup_key_index(database_client)? {
+        missing.push(String::from("the unique index on records (sensor_id, dedup_key)"));
+    }
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Database schema is missing {}, apply the migrations in {}", missing.join(", "), MIGRATIONS_FOLDER))
+    }
+}
+
+/// Function to quote a value of a postgres connection string.
+///
+/// Passwords read from files or the environment may contain spaces and quotes.
+fn quote_connection_value(value: &str) -> String
+{
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Function to establish a TLS encrypted database connection.
+///
+/// # Arguments
+///
+/// * `connection_parameters` - Parameters for the database connection.
+///
+/// # Returns
+///
+/// * `Ok(Client)` - The established connection on success.
+///
+/// * `Err(...)` - If the files for the TLS connection cannot be found, the connection cannot be
+///   established or the user is not authorized for the database.
+///
+pub fn connect_database(connection_parameters: &DatabaseParameters) -> Result<Client, String>
+{
+    let tls_connector = build_tls_connector(connection_parameters)?;
 
     let postgres_connection_string = format!("user={} password={} host={} port={} dbname={} application_name=dblogd",
                                              connection_parameters.username,
-                                             connection_parameters.password,
+                                             quote_connection_value(connection_parameters.password.as_str()),
                                              connection_parameters.hostname,
                                              connection_parameters.port,
                                              connection_parameters.database);
 
+    match Client::connect(postgres_connection_string.as_str(), tls_connector) {
+        Ok(conn) => Ok(conn),
+        Err(err) => Err(format!("Could not establish database connection: \'{}\'", err)),
+    }
+}
 
-    let mut database_connection: Client = match Client::connect(postgres_connection_string.as_str(), tls_connector)
-        {
-            Ok(conn) => conn,
-            Err(err) => {
-                log::error!(target: "dblogd::db", "Could not establish database connection: \'{}\'", err);
-                thread_finish.store(true, Ordering::SeqCst);
-                return;
-            }
-        };
+/// Thread function for the database connection.
+///
+/// This thread establishes a database connection and moves all data in the receive channel to the database.
+/// If the connection is lost, it is reestablished every 5 seconds. Records stay in the channel meanwhile.
+///
+/// The connection and processing parameters are shared with the main thread, which may replace
+/// them at any time. Processing parameters apply to the next record, connection parameters to the
+/// next reconnect.
+///
+/// This function will run until the `thread_finish` parameter was set or the socket is closed by a error.
+/// Once `thread_finish` is set, the records still queued are drained into the database until all
+/// senders are dropped or the `shutdown_timeout` of the processing parameters passed. Records left
+/// in the queue after the deadline and records sent until all senders are dropped are passed to the
+/// quarantine. Records of the `database` quarantine sink are inserted before the thread returns, if
+/// the connection is still available.
+///
+/// # Arguments
+///
+/// * `rx` - The channel to receive the elements to insert from.
+///
+/// * `rejected_rx` - The channel to receive rejected records for the `database` quarantine sink from.
+///
+/// * `quarantine` - The quarantine for records that cannot be inserted.
+///
+/// * `processing_parameters` - Shared parameters for processing records before they are inserted.
+///
+/// * `metrics` - The metrics updated for every insert and reconnect.
+///
+/// * `thread_finish` - Indicates that the thread should finish operation and should return.
+///
+/// * `connection_parameters` - Shared parameters for the database connection.
+///
+/// # Errors
+///
+/// Errors occur when one of the following conditions is met:
+///
+/// * The files for the TLS connection cannot be found.
+///
+/// * The connection cannot be established.
+///
+/// * The the user is not authorized for the database.
+///
+/// These errors will result in the method immediately exiting without raising a exception.
+///
+pub fn database_thread(rx: Receiver<ReceivedRecord>,
+                       rejected_rx: Receiver<RejectedRecord>,
+                       quarantine: Quarantine,
+                       processing_parameters: Arc<RwLock<ProcessingParameters>>,
+                       metrics: Arc<Metrics>,
+                       thread_finish: Arc<AtomicBool>, connection_parameters: Arc<RwLock<DatabaseParameters>>)
+{
+    let mut database_connection: Client = match connect_database(&read_shared(&connection_parameters)) {
+        Ok(conn) => conn,
+        Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
+            log::error!(target: "dblogd::db", "{}", err);
+            thread_finish.store(true, Ordering::SeqCst);
+            return;
+        }
+    };
     log::info!(target: "dblogd::db", "Database connection established!");
+    metrics.database_connected.store(true, Ordering::SeqCst);
     let timeout = time::Duration::from_millis(100);
+    let reconnect_interval = time::Duration::from_secs(5);
+    let mut last_reconnect_attempt = time::Instant::now();
+    let mut current_processing_parameters = read_shared(&processing_parameters);
+    let mut deduplicator = current_processing_parameters.deduplication.as_ref().map(Deduplicator::new);
+    let mut shutdown_deadline: Option<time::Instant> = None;
+    let mut records_flushed: u64 = 0;
+    let mut records_dropped: u64 = 0;
 
-    while !thread_finish.load(Ordering::SeqCst) {
-        let temperature_record = match rx.recv_timeout(timeout) {
+    loop {
+        if shutdown_deadline.is_none() && thread_finish.load(Ordering::SeqCst) {
+            let shutdown_timeout = read_shared(&processing_parameters).shutdown_timeout;
+            log::info!(target: "dblogd::db", "Draining {} queued records within {} seconds!",
+                       metrics.queue_depth.load(Ordering::Relaxed), shutdown_timeout.as_secs());
+            shutdown_deadline = Some(time::Instant::now() + shutdown_timeout);
+        }
+        if shutdown_deadline.is_some_and(|deadline| time::Instant::now() >= deadline) {
+            break;
+        }
+
+        if database_connection.is_closed() {
+            metrics.database_connected.store(false, Ordering::SeqCst);
+            if last_reconnect_attempt.elapsed() < reconnect_interval {
+                thread::sleep(timeout);
+                continue;
+            }
+            last_reconnect_attempt = time::Instant::now();
+            log::warn!(target: "dblogd::db", "Database connection lost, reconnecting!");
+            match connect_database(&read_shared(&connection_parameters)) {
+                Ok(conn) => {
+                    database_connection = conn;
+                    metrics.database_connected.store(true, Ordering::SeqCst);
+                    metrics.database_reconnects.fetch_add(1, Ordering::Relaxed);
+                    log::info!(target: "dblogd::db", "Database connection reestablished!");
+                }
+                Err(err) => {
+                    let _error = log_mdc::insert_scoped("error", err.to_string());
+                    log::error!(target: "dblogd::db", "{}", err);
+                    continue;
+                }
+            };
+        }
+
+        while let Ok(rejected_record) = rejected_rx.try_recv() {
+            match insert_rejected_record(&mut database_connection, &rejected_record) {
+                Ok(_) => {}
+                Err(err) => {
+                    let _error = log_mdc::insert_scoped("error", err.to_string());
+                    log::error!(target: "dblogd::db", "Database quarantine insert failed: \'{}\'", err);
+                }
+            }
+        }
+
+        let received_record = match rx.recv_timeout(timeout) {
             Ok(record) => {
+                metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
                 record
             }
-            Err(_) => {
+            Err(RecvTimeoutError::Timeout) => {
                 continue;
             }
+            Err(RecvTimeoutError::Disconnected) => {
+                break;
+            }
         };
 
-        match insert_temperature_record(&mut database_connection, temperature_record) {
-            Ok(_) => {}
+        let new_processing_parameters = read_shared(&processing_parameters);
+        if new_processing_parameters.deduplication != current_processing_parameters.deduplication {
+            deduplicator = new_processing_parameters.deduplication.as_ref().map(Deduplicator::new);
+        }
+        current_processing_parameters = new_processing_parameters;
+
+        let _sensor_name = log_mdc::insert_scoped("sensor_name", received_record.record.sensor_name.as_str());
+        let _peer_addr = received_record.peer_addr.map(|addr| log_mdc::insert_scoped("peer_addr", addr.to_string()));
+        match insert_received_record(&mut database_connection,
+                                     received_record,
+                                     &mut deduplicator,
+                                     &current_processing_parameters.derived_metrics,
+                                     &quarantine,
+                                     &metrics) {
+            Ok(_) => {
+                if shutdown_deadline.is_some() {
+                    records_flushed += 1;
+                }
+            }
             Err(err) => {
+                if shutdown_deadline.is_some() {
+                    records_dropped += 1;
+                }
+                let _error = log_mdc::insert_scoped("error", err.to_string());
                 log::error!(target: "dblogd::db", "Database insert failed: \'{}\'", err);
                 continue;
             }
         }
     }
+
+    // The socket thread drops the last sender once its open connections finished, so records sent
+    // after the deadline are quarantined as well.
+    for received_record in rx.iter() {
+        metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
+        records_dropped += 1;
+        quarantine.reject(RejectedRecord::new(
+            received_record.payload,
+            received_record.peer_addr,
+            received_record.received_at,
+            RejectionStage::Shutdown,
+            String::from("Shutdown deadline passed before the record was inserted")));
+    }
+    if records_dropped > 0 {
+        log::warn!(target: "dblogd::db", "Shutdown: flushed {} queued records, dropped {} records!", records_flushed, records_dropped);
+    } else {
+        log::info!(target: "dblogd::db", "Shutdown: flushed {} queued records, dropped {} records!", records_flushed, records_dropped);
+    }
+
+    // The `database` quarantine sink is only read by this thread, including the dropped records above.
+    // All connections finished once the record channel is closed, so no further rejected records arrive.
+    let mut rejected_lost: u64 = 0;
+    while let Ok(rejected_record) = rejected_rx.try_recv() {
+        if database_connection.is_closed() {
+            rejected_lost += 1;
+            continue;
+        }
+        match insert_rejected_record(&mut database_connection, &rejected_record) {
+            Ok(_) => {}
+            Err(err) => {
+                rejected_lost += 1;
+                let _error = log_mdc::insert_scoped("error", err.to_string());
+                log::error!(target: "dblogd::db", "Database quarantine insert failed: \'{}\'", err);
+            }
+        }
+    }
+    if rejected_lost > 0 {
+        log::error!(target: "dblogd::db", "Shutdown: lost {} quarantined records, the database is not available!", rejected_lost);
+    }
+    metrics.database_connected.store(false, Ordering::SeqCst);
 }
\ No newline at end of file
@@ -0,0 +1,229 @@
+//! Module to suppress duplicate records resent by devices after a lost connection.
+//!
+//! Records are identified by their sensor and a deduplication key. The key is derived from the
+//! `message_id` of the record, its `seq` number and timestamp or, if the device sends neither, from
+//! a hash of its timestamp, values and units.
+//!
+//! Duplicates are suppressed in memory for a configurable window and optionally by a unique
+//! constraint on `public.records` (see `resources/sql/002_records_deduplication.sql`).
+//!
+use std::collections::{HashSet, VecDeque};
+use std::time;
+
+use serde::{Deserialize, Serialize};
+
+use crate::record::TemperatureRecord;
+
+/// Default length of the in memory deduplication window in seconds.
+const DEFAULT_WINDOW_SECS: u64 = 3600;
+
+/// Default length of the in memory deduplication window in seconds, see `DEFAULT_WINDOW_SECS`.
+fn default_window_secs() -> u64
+{
+    DEFAULT_WINDOW_SECS
+}
+
+/// Default for the database upsert, disabled as it requires the `records.dedup_key` column.
+fn default_database_upsert() -> bool
+{
+    false
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Struct representing the parameters for the duplicate suppression.
+pub struct DeduplicationParameters
+{
+    /// Length of the window in seconds records are remembered in memory.
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    /// Whether the deduplication key is stored in `public.records` and conflicting inserts are skipped.
+    ///
+    /// This requires the unique index on `(sensor_id, dedup_key)`. The constraint is not limited
+    /// to the window, so it is disabled by default: devices reusing their `message_id`s, e.g.
+    /// after a reboot, would have all later records skipped.
+    #[serde(default = "default_database_upsert")]
+    pub database_upsert: bool,
+}
+
+/// Function to compute the deduplication key of a record.
+///
+/// # Arguments
+///
+/// * `record` - The record to compute the key for.
+///
+/// # Returns
+///
+/// The key `id:<message_id>`, `seq:<seq>@<timestamp>` or `sha256:<hash of timestamp, values and units>`
+/// depending on the identifiers sent by the device. The timestamp is part of the `seq` key, so
+/// records of a device restarting its sequence numbers after a reboot are not suppressed.
+///
+pub fn deduplication_key(record: &TemperatureRecord) -> String
+{
+    if let Some(message_id) = &record.message_id {
+        return format!("id:{}", message_id);
+    }
+    if let Some(seq) = record.seq {
+        return format!("seq:{}@{}", seq, record.timestamp.to_rfc3339());
+    }
+
+    let content = format!("{}|{}|{}|{}|{}|{}|{}",
+                          record.timestamp.to_rfc3339(),
+                          record.temperature.to_bits(),
+                          record.temperature_unit.as_str(),
+                          record.humidity.to_bits(),
+                          record.humidity_unit.as_str(),
+                          record.pressure.map_or(String::from("-"), |pressure| pressure.to_bits().to_string()),
+                          record.pressure_unit.as_str());
+    let hash = openssl::sha::sha256(content.as_bytes());
+    let hash_hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256:{}", hash_hex)
+}
+
+/// Struct remembering the records seen within the deduplication window.
+pub struct Deduplicator
+{
+    /// Length of the deduplication window.
+    window: time::Duration,
+    /// Whether duplicates are also suppressed by the database constraint.
+    database_upsert: bool,
+    /// Sensor names and keys currently in the window.
+    seen: HashSet<(String, String)>,
+    /// Sensor names and keys in the order they were seen, used for expiry.
+    expiry: VecDeque<(time::Instant, (String, String))>,
+}
+
+impl Deduplicator {
+    /// Creates a new deduplicator from the parameters.
+    pub fn new(params: &DeduplicationParameters) -> Deduplicator
+    {
+        Deduplicator {
+            window: time::Duration::from_secs(params.window_secs),
+            database_upsert: params.database_upsert,
+            seen: HashSet::new(),
+            expiry: VecDeque::new(),
+        }
+    }
+
+    /// Returns whether duplicates are also suppressed by the database constraint.
+    pub fn database_upsert(&self) -> bool
+    {
+        self.database_upsert
+    }
+
+    /// Checks if the record was already seen in the window.
+    ///
+    /// # Arguments
+    ///
+    /// * `sensor_name` - The name of the sensor that recorded the record.
+    ///
+    /// * `key` - The deduplication key of the record.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the record is a duplicate, `false` if it was not seen within the window.
+    ///
+    pub fn is_duplicate(&mut self, sensor_name: &str, key: &str) -> bool
+    {
+        let now = time::Instant::now();
+        while let Some((seen_at, _)) = self.expiry.front() {
+            if now.duration_since(*seen_at) < self.window {
+                break;
+            }
+            if let Some((_, entry)) = self.expiry.pop_front() {
+                self.seen.remove(&entry);
+            }
+        }
+
+        self.seen.contains(&(String::from(sensor_name), String::from(key)))
+    }
+
+    /// Remembers a record for the window.
+    ///
+    /// Records are only remembered once they are stored, so a record resent after a failed insert
+    /// is not suppressed.
+    ///
+    /// # Arguments
+    ///
+    /// * `sensor_name` - The name of the sensor that recorded the record.
+    ///
+    /// * `key` - The deduplication key of the record.
+    ///
+    pub fn remember(&mut self, sensor_name: &str, key: &str)
+    {
+        let entry = (String::from(sensor_name), String::from(key));
+        if self.seen.insert(entry.clone()) {
+            self.expiry.push_back((time::Instant::now(), entry));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn record(json: &str) -> TemperatureRecord
+    {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn deduplicator(window_secs: u64) -> Deduplicator
+    {
+        Deduplicator::new(&DeduplicationParameters { window_secs, database_upsert: false })
+    }
+
+    #[test]
+    fn deduplication_key_prefers_message_id()
+    {
+        let record = record(r#"{"timestamp": "2019-08-01T12:00:00Z", "sensor_name": "living_room",
+                                "temperature": 21.5, "humidity": 40.0, "seq": 7, "message_id": "a1"}"#);
+        assert_eq!(deduplication_key(&record), "id:a1");
+    }
+
+    #[test]
+    fn deduplication_key_uses_seq_and_timestamp()
+    {
+        let first = record(r#"{"timestamp": "2019-08-01T12:00:00Z", "sensor_name": "living_room",
+                               "celsius": 21.5, "humidity": 40.0, "seq": 7}"#);
+        let after_reboot = record(r#"{"timestamp": "2019-08-01T12:05:00Z", "sensor_name": "living_room",
+                                      "celsius": 21.5, "humidity": 40.0, "seq": 7}"#);
+        assert_eq!(deduplication_key(&first), "seq:7@2019-08-01T12:00:00+00:00");
+        assert_ne!(deduplication_key(&first), deduplication_key(&after_reboot));
+    }
+
+    #[test]
+    fn deduplication_key_hashes_content()
+    {
+        let first = record(r#"{"timestamp": "2019-08-01T12:00:00Z", "sensor_name": "living_room",
+                               "celsius": 21.5, "humidity": 40.0}"#);
+        let resent = record(r#"{"timestamp": "2019-08-01T12:00:00Z", "sensor_name": "living_room",
+                                "celsius": 21.5, "humidity": 40.0}"#);
+        let other_unit = record(r#"{"timestamp": "2019-08-01T12:00:00Z", "sensor_name": "living_room",
+                                    "celsius": 21.5, "temperature_unit": "fahrenheit", "humidity": 40.0}"#);
+        let key = deduplication_key(&first);
+        assert!(key.starts_with("sha256:"));
+        assert_eq!(key.len(), "sha256:".len() + 64);
+        assert_eq!(key, deduplication_key(&resent));
+        assert_ne!(key, deduplication_key(&other_unit));
+    }
+
+    #[test]
+    fn is_duplicate_after_remember()
+    {
+        let mut deduplicator = deduplicator(3600);
+        assert!(!deduplicator.is_duplicate("living_room", "seq:7"));
+        deduplicator.remember("living_room", "seq:7");
+        assert!(deduplicator.is_duplicate("living_room", "seq:7"));
+        assert!(!deduplicator.is_duplicate("kitchen", "seq:7"));
+    }
+
+    #[test]
+    fn is_duplicate_expires_after_window()
+    {
+        let mut deduplicator = deduplicator(0);
+        deduplicator.remember("living_room", "seq:7");
+        assert!(!deduplicator.is_duplicate("living_room", "seq:7"));
+        assert!(deduplicator.seen.is_empty());
+        assert!(deduplicator.expiry.is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+//! Module to compute derived metrics from the temperature and relative humidity of a record.
+//!
+//! The saturation vapour pressure is approximated with the Magnus formula
+//! (`6.112 hPa * exp(17.62 * t / (243.12 °C + t))`), the heat index with the regression of the
+//! US National Weather Service.
+//!
+use serde::{Deserialize, Serialize};
+
+/// Magnus formula coefficient over water.
+const MAGNUS_B: f64 = 17.62;
+/// Magnus formula coefficient over water in degree celsius.
+const MAGNUS_C: f64 = 243.12;
+/// Saturation vapour pressure at 0 degree celsius in pascal.
+const MAGNUS_E0_PA: f64 = 611.2;
+/// Specific gas constant of water vapour in J/(kg K).
+const WATER_VAPOUR_GAS_CONSTANT: f64 = 461.5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+/// Struct representing the derived metrics that should be computed for every record.
+pub struct DerivedMetricsParameters
+{
+    /// Compute the dew point in degree celsius.
+    #[serde(default)]
+    pub dew_point: bool,
+    /// Compute the absolute humidity in gram per cubic metre.
+    #[serde(default)]
+    pub absolute_humidity: bool,
+    /// Compute the vapour pressure deficit in pascal.
+    #[serde(default)]
+    pub vapour_pressure_deficit: bool,
+    /// Compute the heat index in degree celsius.
+    #[serde(default)]
+    pub heat_index: bool,
+}
+
+impl DerivedMetricsParameters {
+    /// Returns `true` if at least one metric is enabled.
+    pub fn any_enabled(&self) -> bool
+    {
+        self.dew_point || self.absolute_humidity || self.vapour_pressure_deficit || self.heat_index
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Struct representing the derived metrics of a single record.
+///
+/// Metrics that are disabled or undefined for the measured values are `None`.
+pub struct DerivedMetrics
+{
+    /// Dew point in degree celsius.
+    pub dew_point_celsius: Option<f64>,
+    /// Absolute humidity in gram per cubic metre.
+    pub absolute_humidity: Option<f64>,
+    /// Vapour pressure deficit in pascal.
+    pub vapour_pressure_deficit_pa: Option<f64>,
+    /// Heat index in degree celsius.
+    pub heat_index_celsius: Option<f64>,
+}
+
+/// Function to compute the saturation vapour pressure of water.
+///
+/// # Arguments
+///
+/// * `celsius` - The temperature in degree celsius.
+///
+/// # Returns
+///
+/// The saturation vapour pressure in pascal.
+///
+fn saturation_vapour_pressure(celsius: f64) -> f64
+{
+    MAGNUS_E0_PA * (MAGNUS_B * celsius / (MAGNUS_C + celsius)).exp()
+}
+
+/// Function to compute the dew point.
+///
+/// Returns `None` for a relative humidity of zero or less, where the dew point is undefined.
+fn dew_point(celsius: f64, humidity_percent: f64) -> Option<f64>
+{
+    if humidity_percent <= 0.0 {
+        return None;
+    }
+    let gamma = (humidity_percent / 100.0).ln() + MAGNUS_B * celsius / (MAGNUS_C + celsius);
+    Some(MAGNUS_C * gamma / (MAGNUS_B - gamma))
+}
+
+/// Function to compute the absolute humidity in gram per cubic metre.
+fn absolute_humidity(celsius: f64, humidity_percent: f64) -> f64
+{
+    let vapour_pressure = saturation_vapour_pressure(celsius) * humidity_percent / 100.0;
+    vapour_pressure / (WATER_VAPOUR_GAS_CONSTANT * (celsius + 273.15)) * 1000.0
+}
+
+/// Function to compute the vapour pressure deficit in pascal.
+fn vapour_pressure_deficit(celsius: f64, humidity_percent: f64) -> f64
+{
+    saturation_vapour_pressure(celsius) * (1.0 - humidity_percent / 100.0)
+}
+
+/// Function to compute the heat index in degree celsius.
+///
+/// Uses the simple formula of Steadman below 80 °F and the Rothfusz regression including the
+/// adjustments for low and high humidity above.
+fn heat_index(celsius: f64, humidity_percent: f64) -> f64
+{
+    let t = celsius * 9.0 / 5.0 + 32.0;
+    let rh = humidity_percent;
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let fahrenheit = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        let mut hi = -42.379
+            + 2.049_015_23 * t
+            + 10.143_331_27 * rh
+            - 0.224_755_41 * t * rh
+            - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh
+            + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh;
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            hi -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            hi += (rh - 85.0) / 10.0 * (87.0 - t) / 5.0;
+        }
+        hi
+    };
+
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+/// Function to compute the enabled derived metrics.
+///
+/// # Arguments
+///
+/// * `params` - The metrics that should be computed.
+///
+/// * `celsius` - The temperature in degree celsius.
+///
+/// * `humidity_percent` - The relative humidity in percent.
+///
+/// # Returns
+///
+/// The derived metrics, disabled metrics are `None`.
+///
+pub fn compute(params: &DerivedMetricsParameters, celsius: f64, humidity_percent: f64) -> DerivedMetrics
+{
+    DerivedMetrics {
+        dew_point_celsius: if params.dew_point { dew_point(celsius, humidity_percent) } else { None },
+        absolute_humidity: if params.absolute_humidity { Some(absolute_humidity(celsius, humidity_percent)) } else { None },
+        vapour_pressure_deficit_pa: if params.vapour_pressure_deficit { Some(vapour_pressure_deficit(celsius, humidity_percent)) } else { None },
+        heat_index_celsius: if params.heat_index { Some(heat_index(celsius, humidity_percent)) } else { None },
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64)
+    {
+        assert!((actual - expected).abs() < tolerance, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn dew_point_matches_reference()
+    {
+        assert_close(dew_point(20.0, 50.0).unwrap(), 9.26, 0.01);
+        assert_close(dew_point(25.0, 100.0).unwrap(), 25.0, 1e-9);
+        assert_eq!(dew_point(20.0, 0.0), None);
+    }
+
+    #[test]
+    fn absolute_humidity_matches_reference()
+    {
+        assert_close(absolute_humidity(20.0, 50.0), 8.62, 0.01);
+        assert_close(absolute_humidity(20.0, 0.0), 0.0, 1e-9);
+    }
+
+    #[test]
+    fn vapour_pressure_deficit_matches_reference()
+    {
+        assert_close(saturation_vapour_pressure(20.0), 2332.6, 0.1);
+        assert_close(vapour_pressure_deficit(20.0, 50.0), 1166.3, 0.1);
+        assert_close(vapour_pressure_deficit(20.0, 100.0), 0.0, 1e-9);
+    }
+
+    #[test]
+    fn heat_index_uses_simple_formula_and_regression()
+    {
+        assert_close(heat_index(20.0, 50.0), 19.36, 0.01);
+        assert_close(heat_index(32.0, 70.0), 40.41, 0.01);
+    }
+
+    #[test]
+    fn compute_only_enabled_metrics()
+    {
+        let params = DerivedMetricsParameters { dew_point: true, heat_index: true, ..Default::default() };
+        let metrics = compute(&params, 20.0, 50.0);
+        assert!(metrics.dew_point_celsius.is_some());
+        assert!(metrics.heat_index_celsius.is_some());
+        assert_eq!(metrics.absolute_humidity, None);
+        assert_eq!(metrics.vapour_pressure_deficit_pa, None);
+    }
+}
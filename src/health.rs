@@ -0,0 +1,112 @@
+//! Module to evaluate the liveness and readiness of the application.
+//!
+//! * Liveness - The socket and database threads are running and no termination was requested.
+//!
+//! * Readiness - The tcp listener is bound, the database connection is established and the queue
+//!   to the database thread is below the configured threshold.
+//!
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+
+use serde::Serialize;
+
+use crate::metrics::Metrics;
+
+#[derive(Serialize, Debug)]
+/// Struct representing the state of a single component.
+pub struct ComponentStatus
+{
+    /// `up` if the component is healthy, `down` otherwise.
+    pub status: &'static str,
+    /// Optional details of the component.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug)]
+/// Struct representing the result of a health check.
+pub struct HealthStatus
+{
+    /// `ok` if all components are healthy, `fail` otherwise.
+    pub status: &'static str,
+    /// The state of each checked component.
+    pub components: BTreeMap<&'static str, ComponentStatus>,
+}
+
+impl HealthStatus {
+    /// Creates the result of a health check, which is `ok` if all components are `up`.
+    ///
+    /// # Arguments
+    ///
+    /// * `components` - The state of each checked component.
+    ///
+    fn from_components(components: BTreeMap<&'static str, ComponentStatus>) -> HealthStatus
+    {
+        let healthy = components.values().all(|component| component.status == "up");
+        HealthStatus {
+            status: if healthy { "ok" } else { "fail" },
+            components,
+        }
+    }
+
+    /// Returns `true` if all components are healthy.
+    pub fn is_ok(&self) -> bool
+    {
+        self.status == "ok"
+    }
+}
+
+/// Function to create the state of a single component.
+///
+/// # Arguments
+///
+/// * `up` - Whether the component is healthy.
+///
+/// * `details` - Optional details of the component, e.g. the queue depth.
+///
+fn component(up: bool, details: Option<serde_json::Value>) -> ComponentStatus
+{
+    ComponentStatus {
+        status: if up { "up" } else { "down" },
+        details,
+    }
+}
+
+/// Function to check the liveness of the application.
+///
+/// # Arguments
+///
+/// * `metrics` - The metrics holding the state of the threads.
+///
+/// * `terminating` - Whether the termination of the application was requested.
+///
+pub fn liveness(metrics: &Metrics, terminating: bool) -> HealthStatus
+{
+    let mut components = BTreeMap::new();
+    components.insert("socket_thread", component(metrics.socket_thread_alive.load(Ordering::SeqCst), None));
+    components.insert("database_thread", component(metrics.database_thread_alive.load(Ordering::SeqCst), None));
+    components.insert("main", component(!terminating, None));
+    HealthStatus::from_components(components)
+}
+
+/// Function to check the readiness of the application.
+///
+/// # Arguments
+///
+/// * `metrics` - The metrics holding the state of the listener, connection and queue.
+///
+/// * `max_queue_depth` - The queue depth at which the application is no longer ready.
+///
+pub fn readiness(metrics: &Metrics, max_queue_depth: i64) -> HealthStatus
+{
+    let queue_depth = metrics.queue_depth.load(Ordering::SeqCst);
+
+    let mut components = BTreeMap::new();
+    components.insert("listener", component(metrics.listener_bound.load(Ordering::SeqCst), None));
+    components.insert("database", component(metrics.database_connected.load(Ordering::SeqCst), None));
+    components.insert("queue", component(queue_depth < max_queue_depth, Some(serde_json::json!({
+        "depth": queue_depth,
+        "threshold": max_queue_depth,
+    }))));
+    HealthStatus::from_components(components)
+}
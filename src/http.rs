@@ -0,0 +1,363 @@
+//!
+//! Module to serve the monitoring endpoints of the application via plain HTTP.
+//!
+//! The following endpoints are available:
+//!
+//! * `GET /metrics` - The metrics of the application in the Prometheus text format.
+//!
+//! * `GET /healthz` - The liveness of the application as JSON.
+//!
+//! * `GET /readyz` - The readiness of the application as JSON.
+//!
+use std::{io, thread, time};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::health::{self, HealthStatus};
+use crate::metrics::Metrics;
+use crate::socket::SocketParameters;
+
+/// Default queue depth at which the application is no longer ready.
+const DEFAULT_READINESS_MAX_QUEUE_DEPTH: i64 = 1000;
+
+/// Time a client has to send the complete request and receive the response.
+const HTTP_REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
+/// Maximum number of http connections handled at the same time.
+const MAX_HTTP_CONNECTIONS: usize = 8;
+
+/// Default queue depth at which `/readyz` reports the application as not ready.
+fn default_readiness_max_queue_depth() -> i64
+{
+    DEFAULT_READINESS_MAX_QUEUE_DEPTH
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Struct representing the parameters for the monitoring HTTP endpoint.
+pub struct HttpParameters
+{
+    /// The parameters for establishing the socket.
+    pub socket_params: SocketParameters,
+    /// The queue depth at which `/readyz` reports the application as not ready.
+    #[serde(default = "default_readiness_max_queue_depth")]
+    pub readiness_max_queue_depth: i64,
+}
+
+/// Struct representing a HTTP response.
+struct Response
+{
+    /// The status line without the protocol, e.g. `200 OK`.
+    status: &'static str,
+    /// The content type of the body.
+    content_type: &'static str,
+    /// The body of the response.
+    body: String,
+}
+
+/// Function to create the response for a request.
+///
+/// # Arguments
+///
+/// * `method` - The method of the request.
+///
+/// * `path` - The path of the request without the query.
+///
+/// * `metrics` - The metrics of the application.
+///
+/// * `terminating` - Whether the termination of the application was requested.
+///
+/// * `params` - Parameters of the HTTP endpoint.
+///
+fn route(method: &str, path: &str, metrics: &Metrics, terminating: bool, params: &HttpParameters) -> Response
+{
+    if method != "GET" {
+        return Response {
+            status: "405 Method Not Allowed",
+            content_type: "text/plain",
+            body: String::from("Method Not Allowed\n"),
+        };
+    }
+
+    match path {
+        "/metrics" => Response {
+            status: "200 OK",
+            content_type: "text/plain; version=0.0.4",
+            body: metrics.render(),
+        },
+        "/healthz" => health_response(health::liveness(metrics, terminating)),
+        "/readyz" => health_response(health::readiness(metrics, params.readiness_max_queue_depth)),
+        _ => Response {
+            status: "404 Not Found",
+            content_type: "text/plain",
+            body: String::from("Not Found\n"),
+        },
+    }
+}
+
+/// Function to create the JSON response for a health check.
+///
+/// The status is `200 OK` if all components are healthy and `503 Service Unavailable` otherwise.
+fn health_response(health_status: HealthStatus) -> Response
+{
+    let status = if health_status.is_ok() { "200 OK" } else { "503 Service Unavailable" };
+    let body = match serde_json::to_string(&health_status) {
+        Ok(body) => body,
+        Err(err) => {
+            log::error!(target: "dblogd::http", "Could not serialize health status: \'{}\'", err);
+            return Response {
+                status: "500 Internal Server Error",
+                content_type: "text/plain",
+                body: String::from("Internal Server Error\n"),
+            };
+        }
+    };
+    Response {
+        status,
+        content_type: "application/json",
+        body,
+    }
+}
+
+///
+/// Function handling a single HTTP request.
+///
+/// Only the request line is evaluated, the connection is closed after the response.
+/// The whole request has to be received before the `timeout` elapses, regardless of how many
+/// reads it takes.
+///
+/// # Arguments
+///
+/// * `stream` - The TCP stream to the HTTP client.
+///
+/// * `metrics` - The metrics of the application.
+///
+/// * `thread_finish` - Indicates that the termination of the application was requested.
+///
+/// * `params` - Parameters of the HTTP endpoint.
+///
+/// * `timeout` - The time the client has to send the request.
+///
+/// # Errors
+///
+/// Errors occur when the request cannot be read in time or the response cannot be written.
+/// These errors are returned to the caller.
+///
+fn handle_http_stream(mut stream: TcpStream, metrics: &Metrics, thread_finish: &AtomicBool, params: &HttpParameters, timeout: time::Duration) -> io::Result<()>
+{
+    let deadline = time::Instant::now() + timeout;
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut request_buf: [u8; 1024] = [0; 1024];
+    let mut request_len = 0;
+    while request_len < request_buf.len() {
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+        if remaining == time::Duration::from_secs(0) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request not received before the deadline"));
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        let bytes_read = stream.read(&mut request_buf[request_len..])?;
+        if bytes_read == 0 {
+            break;
+        }
+        request_len += bytes_read;
+        if request_buf[..request_len].windows(4).any(|window| window == b"\r\n\r\n") {
+            break;
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request_buf[..request_len]);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("");
+    let path = request_line.next().unwrap_or("").split('?').next().unwrap_or("");
+
+    let response = route(method, path, metrics, thread_finish.load(Ordering::SeqCst), params);
+    log::debug!(target: "dblogd::http", "{} {} - {}", method, path, response.status);
+
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           response.status,
+           response.content_type,
+           response.body.len(),
+           response.body)?;
+    stream.flush()
+}
+
+/// Guard decrementing the number of active http connections when it is dropped.
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self)
+    {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Thread function for the monitoring HTTP endpoint.
+///
+/// This function accepts incoming connections and answers each request on its own thread, so a
+/// slow client cannot block the other endpoints. At most `MAX_HTTP_CONNECTIONS` requests are
+/// handled at the same time, further connections are closed immediately.
+///
+/// This function will run until the `thread_finish` parameter was set.
+///
+/// # Arguments
+///
+/// * `metrics` - The metrics of the application.
+///
+/// * `thread_finish` - Indicates that the thread should finish operation and should return.
+///
+/// * `params` - Parameters for the HTTP socket.
+///
+/// # Errors
+///
+/// Errors occur when the socket cannot be created or listened to.
+///
+/// These errors will result in the method immediately exiting without raising a exception.
+/// Only the monitoring endpoint stops, the rest of the application keeps running.
+///
+pub fn thread_http_server(metrics: Arc<Metrics>, thread_finish: Arc<AtomicBool>, params: HttpParameters)
+{
+    let params = Arc::new(params);
+    let active_connections = Arc::new(AtomicUsize::new(0));
+    let tcp_listener = match TcpListener::bind(format!("{}:{}", params.socket_params.address, params.socket_params.port)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
+            log::error!(target: "dblogd::http", "Could not open http listener: \'{}\'", err);
+            return;
+        }
+    };
+    match tcp_listener.set_nonblocking(true) {
+        Ok(_) => {}
+        Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
+            log::error!(target: "dblogd::http", "Could not set http listener nonblocking: \'{}\'", err);
+            return;
+        }
+    };
+    match tcp_listener.local_addr() {
+        Ok(res) => {
+            log::info!(target: "dblogd::http", "Http Addr: \'{}\'", res);
+        }
+        Err(err) => {
+            log::warn!(target: "dblogd::http", "Could not get http socket address: \'{}\'", err);
+        }
+    }
+
+    while !thread_finish.load(Ordering::SeqCst) {
+        match tcp_listener.accept() {
+            Ok((stream, addr)) => {
+                if !params.socket_params.is_allowed(addr.ip()) {
+                    log::debug!(target: "dblogd::http", "Closing http connection from denied address \'{}\'!", addr);
+                    continue;
+                }
+                if active_connections.fetch_add(1, Ordering::SeqCst) >= MAX_HTTP_CONNECTIONS {
+                    active_connections.fetch_sub(1, Ordering::SeqCst);
+                    log::warn!(target: "dblogd::http", "Closing http connection from \'{}\', too many open connections!", addr);
+                    continue;
+                }
+                let guard = ConnectionGuard(active_connections.clone());
+                let metrics = metrics.clone();
+                let params = params.clone();
+                let thread_finish = thread_finish.clone();
+                let spawned = thread::Builder::new()
+                    .name("http-request".to_string())
+                    .spawn(move || {
+                        let _guard = guard;
+                        match handle_http_stream(stream, &metrics, &thread_finish, &params, HTTP_REQUEST_TIMEOUT) {
+                            Ok(_) => {}
+                            Err(err) => {
+                                log::warn!(target: "dblogd::http", "Could not answer http request from \'{}\': \'{}\'", addr, err);
+                            }
+                        };
+                    });
+                if let Err(err) = spawned {
+                    log::error!(target: "dblogd::http", "Could not spawn http request thread: \'{}\'", err);
+                }
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(time::Duration::from_millis(100));
+            }
+            Err(err) => {
+                log::error!(target: "dblogd::http", "Could not accept http connection: \'{}\'", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn http_params() -> HttpParameters
+    {
+        HttpParameters {
+            socket_params: SocketParameters {
+                address: String::from("127.0.0.1"),
+                port: 0,
+                allow: Vec::new(),
+                deny: Vec::new(),
+            },
+            readiness_max_queue_depth: DEFAULT_READINESS_MAX_QUEUE_DEPTH,
+        }
+    }
+
+    fn connect() -> (TcpStream, TcpStream)
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn handle_http_stream_answers_request()
+    {
+        let (mut client, server) = connect();
+        client.write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        handle_http_stream(server, &Metrics::new(), &AtomicBool::new(false), &http_params(), time::Duration::from_secs(1)).unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 "));
+        assert!(response.contains("Content-Type: application/json"));
+    }
+
+    #[test]
+    fn handle_http_stream_reports_termination()
+    {
+        let (mut client, server) = connect();
+        client.write_all(b"GET /healthz HTTP/1.1\r\n\r\n").unwrap();
+        handle_http_stream(server, &Metrics::new(), &AtomicBool::new(true), &http_params(), time::Duration::from_secs(1)).unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert!(response.contains("\"main\":{\"status\":\"down\""));
+    }
+
+    #[test]
+    fn handle_http_stream_times_out_slow_request()
+    {
+        let (mut client, server) = connect();
+        let sender = thread::spawn(move || {
+            for byte in b"GET /metrics HTTP/1.1\r\n".iter() {
+                if client.write_all(&[*byte]).is_err() {
+                    break;
+                }
+                thread::sleep(time::Duration::from_millis(100));
+            }
+        });
+
+        let started = time::Instant::now();
+        let err = handle_http_stream(server, &Metrics::new(), &AtomicBool::new(false), &http_params(), time::Duration::from_millis(500)).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::TimedOut || err.kind() == io::ErrorKind::WouldBlock);
+        assert!(started.elapsed() < time::Duration::from_secs(1));
+        sender.join().unwrap();
+    }
+}
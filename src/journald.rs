@@ -0,0 +1,213 @@
+//! Module implementing a log4rs appender that writes to the systemd journal.
+//!
+//! Entries are sent with the native journal protocol to `/run/systemd/journal/socket`, see
+//! `systemd.journal-fields(7)`. Besides the message and its priority, every entry carries the
+//! following fields:
+//!
+//! * `DBLOGD_TARGET` - The target of the log message, e.g. `dblogd::db`.
+//!
+//! * The structured fields of the message, e.g. `SENSOR_NAME`, `PEER_ADDR` and `ERROR`.
+//!
+use std::error::Error;
+use std::fmt;
+use std::os::unix::net::UnixDatagram;
+
+use log::{Level, Record};
+use log4rs::append::Append;
+
+/// The socket of the journal for entries sent with the native protocol.
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The error code of Linux for datagrams exceeding the size limit of the socket.
+const EMSGSIZE: i32 = 90;
+
+/// Number of bytes of the message kept if an entry exceeds the size limit of the socket.
+const TRUNCATED_MESSAGE_SIZE: usize = 8192;
+
+/// Appender sending log messages to the systemd journal.
+pub struct JournaldAppender
+{
+    /// The unbound socket used to send the entries.
+    socket: UnixDatagram,
+}
+
+impl fmt::Debug for JournaldAppender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JournaldAppender").finish()
+    }
+}
+
+impl JournaldAppender {
+    /// Creates a new appender.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal socket does not exist, e.g. if systemd is not running.
+    ///
+    pub fn new() -> Result<JournaldAppender, String>
+    {
+        let socket = match UnixDatagram::unbound() {
+            Ok(socket) => socket,
+            Err(err) => {
+                return Err(format!("Could not create journal socket: \'{}\'", err));
+            }
+        };
+        match socket.connect(JOURNAL_SOCKET) {
+            Ok(_) => Ok(JournaldAppender { socket }),
+            Err(err) => Err(format!("Could not connect to the journal at \'{}\': \'{}\'", JOURNAL_SOCKET, err)),
+        }
+    }
+}
+
+/// Function to map a log level to a syslog priority.
+fn priority(level: Level) -> u8
+{
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Function to convert the name of a structured field to a valid journal field name.
+///
+/// Journal field names consist of upper case letters, digits and underscores and must not start
+/// with an underscore or a digit.
+fn field_name(name: &str) -> String
+{
+    let field_name: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    match field_name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => field_name,
+        _ => format!("DBLOGD{}", field_name),
+    }
+}
+
+/// Function to append a field to a journal entry.
+///
+/// Values containing a line break are written in the binary format of the protocol.
+fn append_field(entry: &mut Vec<u8>, name: &str, value: &str)
+{
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// Function to shorten a message to at most `max_len` bytes without splitting a character.
+fn truncate(message: &str, max_len: usize) -> &str
+{
+    if message.len() <= max_len {
+        return message;
+    }
+    let mut end = max_len;
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    &message[..end]
+}
+
+/// Function to create the journal entry of a log record.
+///
+/// # Arguments
+///
+/// * `record` - The log record.
+///
+/// * `message` - The formatted message of the record.
+///
+fn build_entry(record: &Record, message: &str) -> Vec<u8>
+{
+    let mut entry = vec![];
+    append_field(&mut entry, "MESSAGE", message);
+    append_field(&mut entry, "PRIORITY", priority(record.level()).to_string().as_str());
+    append_field(&mut entry, "SYSLOG_IDENTIFIER", "dblogd");
+    append_field(&mut entry, "DBLOGD_TARGET", record.target());
+    if let Some(file) = record.file() {
+        append_field(&mut entry, "CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        append_field(&mut entry, "CODE_LINE", line.to_string().as_str());
+    }
+    log_mdc::iter(|key, value| append_field(&mut entry, field_name(key).as_str(), value));
+    entry
+}
+
+impl Append for JournaldAppender {
+    /// Sends the record to the journal.
+    ///
+    /// Entries exceeding the size limit of the socket are written to stderr completely and sent to
+    /// the journal with a truncated message.
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let message = record.args().to_string();
+        match self.socket.send(&build_entry(record, message.as_str())) {
+            Ok(_) => Ok(()),
+            Err(err) if err.raw_os_error() == Some(EMSGSIZE) => {
+                eprintln!("{} - {} - {}", record.level(), record.target(), message);
+                let truncated = format!("{} [truncated, the complete message was written to stderr]",
+                                        truncate(message.as_str(), TRUNCATED_MESSAGE_SIZE));
+                self.socket.send(&build_entry(record, truncated.as_str()))?;
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn field_name_upper_cases_and_replaces_invalid_characters()
+    {
+        assert_eq!(field_name("sensor_name"), "SENSOR_NAME");
+        assert_eq!(field_name("peer-addr"), "PEER_ADDR");
+        assert_eq!(field_name("tls.version2"), "TLS_VERSION2");
+    }
+
+    #[test]
+    fn field_name_prefixes_invalid_first_characters()
+    {
+        assert_eq!(field_name("_source"), "DBLOGD_SOURCE");
+        assert_eq!(field_name("2fa"), "DBLOGD2FA");
+        assert_eq!(field_name(""), "DBLOGD");
+    }
+
+    #[test]
+    fn append_field_writes_single_line_values()
+    {
+        let mut entry = vec![];
+        append_field(&mut entry, "MESSAGE", "Ready");
+        append_field(&mut entry, "PRIORITY", "6");
+        assert_eq!(entry, b"MESSAGE=Ready\nPRIORITY=6\n".to_vec());
+    }
+
+    #[test]
+    fn append_field_writes_multi_line_values_length_prefixed()
+    {
+        let mut entry = vec![];
+        append_field(&mut entry, "MESSAGE", "a\nb");
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(entry, expected);
+    }
+
+    #[test]
+    fn truncate_keeps_characters_whole()
+    {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("abcdef", 3), "abc");
+        assert_eq!(truncate("aä", 2), "a");
+    }
+}
@@ -0,0 +1,345 @@
+//! Module limiting the connections and records accepted by the ingest socket.
+//!
+//! The following limits are supported, each disabled unless configured:
+//!
+//! * `max_connections` - Connections accepted while this many connections are open are closed
+//!   immediately. Without this limit the socket accepts as many connections as it has connection
+//!   threads.
+//!
+//! * `max_connections_per_ip` - The same limit for the connections of a single peer address.
+//!
+//! * `connection_rate` - A token bucket limiting the records per second of each connection.
+//!
+//! * `sensor_rate` - A token bucket limiting the records per second of each sensor, shared by all
+//!   connections sending records of the sensor.
+//!
+//! Connections exceeding a rate are either throttled, by not reading further data until a token
+//! is available, or disconnected, see `LimitAction`.
+//!
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+use serde::{Deserialize, Serialize};
+
+/// Number of sensor buckets kept before full buckets are discarded.
+const MAX_SENSOR_BUCKETS: usize = 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Enum representing the action taken if a connection exceeds a rate.
+pub enum LimitAction
+{
+    /// Stop reading from the connection until a token is available.
+    Throttle,
+    /// Close the connection.
+    Disconnect,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// Struct representing the parameters of a token bucket.
+pub struct RateParameters
+{
+    /// The number of records per second added to the bucket.
+    pub records_per_second: f64,
+    /// The maximum number of records accepted at once.
+    pub burst: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+/// Struct representing the limits of the ingest socket.
+pub struct LimitParameters
+{
+    /// Maximum number of open connections, `0` limits them to the default number of connection threads.
+    pub max_connections: usize,
+    /// Maximum number of open connections per peer address, `0` disables the limit.
+    pub max_connections_per_ip: usize,
+    /// Rate limit of the records of each connection or `None` if disabled.
+    pub connection_rate: Option<RateParameters>,
+    /// Rate limit of the records of each sensor or `None` if disabled.
+    pub sensor_rate: Option<RateParameters>,
+    /// The action taken if a connection exceeds a rate.
+    pub exceeded_action: LimitAction,
+}
+
+impl Default for LimitParameters {
+    fn default() -> Self {
+        LimitParameters {
+            max_connections: 0,
+            max_connections_per_ip: 0,
+            connection_rate: None,
+            sensor_rate: None,
+            exceeded_action: LimitAction::Throttle,
+        }
+    }
+}
+
+/// Struct representing a token bucket.
+struct TokenBucket
+{
+    /// The number of tokens added per second.
+    rate: f64,
+    /// The maximum number of tokens.
+    burst: f64,
+    /// The number of tokens currently available.
+    tokens: f64,
+    /// Time the tokens were last refilled.
+    last_refill: time::Instant,
+}
+
+impl TokenBucket {
+    /// Creates a new, full token bucket.
+    fn new(params: &RateParameters) -> TokenBucket
+    {
+        let burst = f64::from(params.burst.max(1));
+        TokenBucket {
+            rate: params.records_per_second,
+            burst,
+            tokens: burst,
+            last_refill: time::Instant::now(),
+        }
+    }
+
+    /// Adds the tokens accumulated since the last refill.
+    fn refill(&mut self)
+    {
+        let now = time::Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.last_refill).as_secs_f64() * self.rate).min(self.burst);
+        self.last_refill = now;
+    }
+
+    /// Takes a token from the bucket.
+    ///
+    /// # Returns
+    ///
+    /// `None` if a token was taken, otherwise the time until the next token is available.
+    ///
+    fn take(&mut self) -> Option<time::Duration>
+    {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else if self.rate > 0.0 {
+            Some(time::Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        } else {
+            Some(time::Duration::from_secs(1))
+        }
+    }
+
+    /// Returns whether the bucket is full, i.e. its sensor did not send records recently.
+    fn is_full(&mut self) -> bool
+    {
+        self.refill();
+        self.tokens >= self.burst
+    }
+}
+
+/// Struct counting the open connections.
+struct ConnectionCounts
+{
+    /// The number of open connections.
+    total: usize,
+    /// The number of open connections per peer address.
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Struct enforcing the limits of the ingest socket, shared by all connections.
+pub struct Limiter
+{
+    /// The configured limits.
+    params: LimitParameters,
+    /// The open connections.
+    connections: Mutex<ConnectionCounts>,
+    /// The token buckets of the sensors.
+    sensor_buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl Limiter {
+    /// Creates a new limiter without open connections.
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - The configured limits.
+    ///
+    pub fn new(params: LimitParameters) -> Limiter
+    {
+        Limiter {
+            params,
+            connections: Mutex::new(ConnectionCounts { total: 0, per_ip: HashMap::new() }),
+            sensor_buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers a new connection if the connection limits allow it.
+    ///
+    /// # Arguments
+    ///
+    /// * `limiter` - The shared limiter.
+    ///
+    /// * `peer_ip` - The address of the peer, if known.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ConnectionLimiter)` - The limiter of the connection, which unregisters the connection
+    ///   when it is dropped.
+    ///
+    /// * `Err(...)` - The name of the exceeded limit, `max_connections` or `max_connections_per_ip`.
+    ///
+    pub fn open_connection(limiter: &Arc<Limiter>, peer_ip: Option<IpAddr>) -> Result<ConnectionLimiter, &'static str>
+    {
+        let params = &limiter.params;
+        {
+            let mut connections = match limiter.connections.lock() {
+                Ok(connections) => connections,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            if params.max_connections > 0 && connections.total >= params.max_connections {
+                return Err("max_connections");
+            }
+            if let Some(peer_ip) = peer_ip {
+                let ip_connections = connections.per_ip.get(&peer_ip).copied().unwrap_or(0);
+                if params.max_connections_per_ip > 0 && ip_connections >= params.max_connections_per_ip {
+                    return Err("max_connections_per_ip");
+                }
+                connections.per_ip.insert(peer_ip, ip_connections + 1);
+            }
+            connections.total += 1;
+        }
+
+        Ok(ConnectionLimiter {
+            limiter: Arc::clone(limiter),
+            peer_ip,
+            connection_bucket: params.connection_rate.as_ref().map(TokenBucket::new),
+        })
+    }
+}
+
+/// Struct enforcing the limits of a single connection.
+pub struct ConnectionLimiter
+{
+    /// The shared limiter.
+    limiter: Arc<Limiter>,
+    /// The address of the peer, if known.
+    peer_ip: Option<IpAddr>,
+    /// The token bucket of the connection or `None` if not limited.
+    connection_bucket: Option<TokenBucket>,
+}
+
+impl ConnectionLimiter {
+    /// Returns the action taken if the connection exceeds a rate.
+    pub fn exceeded_action(&self) -> LimitAction
+    {
+        self.limiter.params.exceeded_action
+    }
+
+    /// Takes a token for a record received on the connection.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the record is within the rate, otherwise the time until the next token is available.
+    ///
+    pub fn take_connection_token(&mut self) -> Option<time::Duration>
+    {
+        match &mut self.connection_bucket {
+            Some(bucket) => bucket.take(),
+            None => None,
+        }
+    }
+
+    /// Takes a token for a record of a sensor.
+    ///
+    /// # Arguments
+    ///
+    /// * `sensor_name` - The name of the sensor of the record.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the record is within the rate, otherwise the time until the next token is available.
+    ///
+    pub fn take_sensor_token(&self, sensor_name: &str) -> Option<time::Duration>
+    {
+        let params = match &self.limiter.params.sensor_rate {
+            Some(params) => params,
+            None => return None,
+        };
+        let mut sensor_buckets = match self.limiter.sensor_buckets.lock() {
+            Ok(sensor_buckets) => sensor_buckets,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if sensor_buckets.len() >= MAX_SENSOR_BUCKETS && !sensor_buckets.contains_key(sensor_name) {
+            sensor_buckets.retain(|_, bucket| !bucket.is_full());
+        }
+        sensor_buckets.entry(String::from(sensor_name))
+            .or_insert_with(|| TokenBucket::new(params))
+            .take()
+    }
+}
+
+impl Drop for ConnectionLimiter {
+    fn drop(&mut self) {
+        let mut connections = match self.limiter.connections.lock() {
+            Ok(connections) => connections,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        connections.total -= 1;
+        if let Some(peer_ip) = self.peer_ip {
+            if let Some(ip_connections) = connections.per_ip.get_mut(&peer_ip) {
+                *ip_connections -= 1;
+                if *ip_connections == 0 {
+                    connections.per_ip.remove(&peer_ip);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn bucket(records_per_second: f64, burst: u32) -> TokenBucket
+    {
+        TokenBucket::new(&RateParameters { records_per_second, burst })
+    }
+
+    #[test]
+    fn take_allows_burst()
+    {
+        let mut bucket = bucket(0.01, 3);
+        assert!(bucket.is_full());
+        for _ in 0..3 {
+            assert_eq!(bucket.take(), None);
+        }
+        assert!(!bucket.is_full());
+    }
+
+    #[test]
+    fn take_returns_time_until_next_token()
+    {
+        let mut bucket = bucket(0.01, 1);
+        assert_eq!(bucket.take(), None);
+        let wait = bucket.take().unwrap();
+        assert!(wait > time::Duration::from_secs(99) && wait <= time::Duration::from_secs(100));
+    }
+
+    #[test]
+    fn take_refills_tokens()
+    {
+        let mut bucket = bucket(1000.0, 1);
+        assert_eq!(bucket.take(), None);
+        std::thread::sleep(time::Duration::from_millis(5));
+        assert_eq!(bucket.take(), None);
+    }
+
+    #[test]
+    fn take_without_rate_waits_one_second()
+    {
+        let mut bucket = bucket(0.0, 0);
+        assert_eq!(bucket.take(), None);
+        assert_eq!(bucket.take(), Some(time::Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,349 @@
+//! Module to build the logging configuration of the application.
+//!
+//! The logging is either configured by a native log4rs configuration file in `logging_config_file`
+//! or built from the `logging` section of the configuration.
+//!
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time;
+
+use log4rs::append::console::ConsoleAppender;
+use log4rs::append::rolling_file::RollingFileAppender;
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
+use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
+use log4rs::config::{Appender, Config, Logger, Root};
+use log4rs::encode::Encode;
+use log4rs::encode::json::JsonEncoder;
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::file::{Deserializers, RawConfig};
+use log::{LevelFilter, Record};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Configuration;
+use crate::journald::JournaldAppender;
+
+/// The log levels ordered by verbosity, used to apply the `-v` and `-q` flags.
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Enum representing where log messages are written to.
+pub enum LogMode
+{
+    /// Log messages are written to stdout and a rolling log file in the `logging_folder`.
+    Standard,
+    /// Log messages are sent to the systemd journal with their structured fields, stdout and the
+    /// log file are not used.
+    Journald,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Enum representing the format of the log lines.
+pub enum LogFormat
+{
+    /// Lines formatted with the configured `pattern`.
+    Pattern,
+    /// One JSON object per line with the time, level, target, thread, message and the structured
+    /// fields like `peer_addr`, `sensor_name` and `error` in `mdc`.
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+/// Struct representing the parameters of the built-in logging configuration.
+pub struct LoggingParameters
+{
+    /// The level of all `dblogd` targets without an explicit level.
+    pub level: LevelFilter,
+    /// The levels of single targets, e.g. `dblogd::db` or third party crates like `postgres`.
+    pub levels: BTreeMap<String, LevelFilter>,
+    /// Where log messages are written to.
+    pub mode: LogMode,
+    /// The format of the log lines, not used by the `journald` mode.
+    pub format: LogFormat,
+    /// The log4rs pattern of the log lines, used by the `pattern` format.
+    pub pattern: String,
+    /// The size in bytes the log file is rotated at.
+    pub rotation_size: u64,
+    /// The number of rotated log files that are retained.
+    pub retained_files: u32,
+}
+
+impl Default for LoggingParameters {
+    fn default() -> Self {
+        LoggingParameters {
+            level: LevelFilter::Info,
+            levels: BTreeMap::new(),
+            mode: LogMode::Standard,
+            format: LogFormat::Pattern,
+            pattern: String::from("{d(%Y-%m-%d %H:%M:%S %Z)(utc)} - {h({l})} - {t} - {T} - {m}{n}"),
+            rotation_size: 1000000,
+            retained_files: 5,
+        }
+    }
+}
+
+/// Function to create the encoder of the log lines.
+///
+/// # Arguments
+///
+/// * `params` - The parameters of the built-in logging configuration.
+///
+/// # Returns
+///
+/// The encoder for the configured format.
+///
+fn build_encoder(params: &LoggingParameters) -> Box<dyn Encode>
+{
+    match params.format {
+        LogFormat::Pattern => Box::new(PatternEncoder::new(params.pattern.as_str())),
+        LogFormat::Json => Box::new(JsonEncoder::new()),
+    }
+}
+
+/// Function to adjust a log level by the verbosity given on the command line.
+///
+/// # Arguments
+///
+/// * `level` - The configured level.
+///
+/// * `verbosity` - The number of `-v` flags minus the number of `-q` flags.
+///
+/// # Returns
+///
+/// The adjusted level, limited to the range from `off` to `trace`.
+///
+fn adjust_level(level: LevelFilter, verbosity: i64) -> LevelFilter
+{
+    let index = LEVELS.iter().position(|candidate| *candidate == level).unwrap_or(3) as i64;
+    LEVELS[(index + verbosity).clamp(0, LEVELS.len() as i64 - 1) as usize]
+}
+
+/// Function to create the appenders of the `standard` mode.
+///
+/// # Arguments
+///
+/// * `configuration` - The configuration of the application.
+///
+/// # Returns
+///
+/// * `Ok(Vec<Appender>)` - The stdout and the rolling file appender on success.
+///
+/// * `Err(...)` - If the rolling log file cannot be created.
+///
+fn build_standard_appenders(configuration: &Configuration) -> Result<Vec<Appender>, String>
+{
+    let params = &configuration.logging;
+    let rolling_logger_file = format!("{}/dblogd.log", configuration.logging_folder);
+    let rolling_logger_file_pattern = format!("{}/dblogd.{}.log", configuration.logging_folder, "{}");
+
+    let stdout = ConsoleAppender::builder()
+        .encoder(build_encoder(params)).build();
+
+    let roller = match FixedWindowRoller::builder()
+        .base(1)
+        .build(rolling_logger_file_pattern.as_str(), params.retained_files) {
+        Ok(roller) => roller,
+        Err(err) => {
+            return Err(format!("Could not create log file roller: \'{}\'", err));
+        }
+    };
+    let rolling_log_file = match RollingFileAppender::builder()
+        .encoder(build_encoder(params))
+        .build(
+            rolling_logger_file.as_str(),
+            Box::new(CompoundPolicy::new(
+                Box::new(SizeTrigger::new(params.rotation_size)),
+                Box::new(roller),
+            )),
+        ) {
+        Ok(logger) => logger,
+        Err(err) => {
+            return Err(format!("Could not create rolling file logger: \'{}\'", err));
+        }
+    };
+
+    Ok(vec![
+        Appender::builder().build("stdout", Box::new(stdout)),
+        Appender::builder().build("rolling_log_file", Box::new(rolling_log_file)),
+    ])
+}
+
+/// Function to build the logging configuration.
+///
+/// Log messages are written to stdout and a rolling log file in the `logging_folder`, or to the
+/// systemd journal in the `journald` mode.
+///
+/// # Arguments
+///
+/// * `configuration` - The configuration of the application.
+///
+/// * `verbosity` - The number of `-v` flags minus the number of `-q` flags, shifting all
+///   configured levels.
+///
+/// # Returns
+///
+/// * `Ok(Config)` - The logging configuration on success.
+///
+/// * `Err(...)` - If the rolling log file or the journal cannot be opened or the configuration is
+///   invalid.
+///
+pub fn build_log_config(configuration: &Configuration, verbosity: i64) -> Result<Config, String>
+{
+    let params = &configuration.logging;
+    let appenders = match params.mode {
+        LogMode::Standard => build_standard_appenders(configuration)?,
+        LogMode::Journald => vec![Appender::builder().build("journald", Box::new(JournaldAppender::new()?))],
+    };
+    let appender_names: Vec<String> = appenders.iter().map(|appender| String::from(appender.name())).collect();
+
+    let mut config_builder = Config::builder()
+        .appenders(appenders)
+        .logger(Logger::builder()
+            .appenders(&appender_names)
+            .additive(false)
+            .build("dblogd", adjust_level(params.level, verbosity)));
+
+    for (target, level) in &params.levels {
+        config_builder = config_builder.logger(Logger::builder()
+            .appenders(&appender_names)
+            .additive(false)
+            .build(target.as_str(), adjust_level(*level, verbosity)));
+    }
+
+    match config_builder
+        .build(Root::builder()
+            .appender(appender_names[0].as_str())
+            .build(LevelFilter::Warn)) {
+        Ok(config) => Ok(config),
+        Err(err) => Err(format!("{}", err)),
+    }
+}
+
+/// Function to load the logging configuration.
+///
+/// The log4rs configuration file is used if `logging_config_file` is set, the built-in
+/// configuration otherwise. The verbosity only applies to the built-in configuration.
+///
+/// # Arguments
+///
+/// * `configuration` - The configuration of the application.
+///
+/// * `verbosity` - The number of `-v` flags minus the number of `-q` flags.
+///
+/// # Returns
+///
+/// * `Ok(Config)` - The logging configuration on success.
+///
+/// * `Err(...)` - If the configuration file cannot be loaded or the built-in configuration is invalid.
+///
+pub fn load_log_config(configuration: &Configuration, verbosity: i64) -> Result<Config, String>
+{
+    match &configuration.logging_config_file {
+        Some(logging_config_file) => match log4rs::load_config_file(logging_config_file, Deserializers::default()) {
+            Ok(config) => Ok(config),
+            Err(err) => Err(format!("Could not load logging configuration file \'{}\': \'{}\'", logging_config_file, err)),
+        },
+        None => build_log_config(configuration, verbosity),
+    }
+}
+
+/// Function to validate the logging configuration without creating any log file.
+///
+/// The log4rs configuration file is only parsed. For the built-in configuration the pattern, the
+/// rotated file names, the `logging_folder` and in the `journald` mode the journal socket are
+/// checked.
+///
+/// # Arguments
+///
+/// * `configuration` - The configuration of the application.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the logging configuration is valid.
+///
+/// * `Err(...)` - If the configuration file cannot be parsed or the built-in configuration is invalid.
+///
+pub fn validate_log_config(configuration: &Configuration) -> Result<(), String>
+{
+    if let Some(logging_config_file) = &configuration.logging_config_file {
+        let source = match fs::read_to_string(logging_config_file) {
+            Ok(source) => source,
+            Err(err) => {
+                return Err(format!("Could not read logging configuration file \'{}\': \'{}\'", logging_config_file, err));
+            }
+        };
+        return match serde_yaml::from_str::<RawConfig>(source.as_str()) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Could not parse logging configuration file \'{}\': \'{}\'", logging_config_file, err)),
+        };
+    }
+
+    let params = &configuration.logging;
+    if params.format == LogFormat::Pattern {
+        validate_pattern(params.pattern.as_str())?;
+    }
+    match params.mode {
+        LogMode::Standard => {
+            let logging_folder = Path::new(configuration.logging_folder.as_str());
+            if logging_folder.exists() && !logging_folder.is_dir() {
+                return Err(format!("Logging folder \'{}\' is not a directory", configuration.logging_folder));
+            }
+            let rolling_logger_file_pattern = format!("{}/dblogd.{}.log", configuration.logging_folder, "{}");
+            match FixedWindowRoller::builder().base(1).build(rolling_logger_file_pattern.as_str(), params.retained_files) {
+                Ok(_) => Ok(()),
+                Err(err) => Err(format!("Could not create log file roller: \'{}\'", err)),
+            }
+        }
+        LogMode::Journald => JournaldAppender::new().map(|_| ()),
+    }
+}
+
+/// Function to validate a log4rs pattern by formatting a sample record.
+///
+/// Invalid parts of a pattern are not rejected by log4rs but written as `{ERROR: ...}` into every
+/// log line.
+fn validate_pattern(pattern: &str) -> Result<(), String>
+{
+    let encoder = PatternEncoder::new(pattern);
+    let mut writer = SimpleWriter(Vec::new());
+    let encoded = encoder.encode(&mut writer, &Record::builder()
+        .args(format_args!("check"))
+        .target("dblogd")
+        .build());
+    if let Err(err) = encoded {
+        return Err(format!("Could not format log line with pattern \'{}\': \'{}\'", pattern, err));
+    }
+    let line = String::from_utf8_lossy(&writer.0);
+    match line.find("{ERROR: ") {
+        Some(start) => Err(format!("Invalid log pattern \'{}\': \'{}\'", pattern, &line[start..].trim_end())),
+        None => Ok(()),
+    }
+}
+
+/// Function to read the time the logging configuration file was last modified.
+///
+/// # Arguments
+///
+/// * `configuration` - The configuration of the application.
+///
+/// # Returns
+///
+/// The modification time of the file, or `None` if no file is configured or it cannot be read.
+///
+pub fn log_config_file_modified(configuration: &Configuration) -> Option<time::SystemTime>
+{
+    configuration.logging_config_file.as_ref()
+        .and_then(|logging_config_file| fs::metadata(logging_config_file).and_then(|metadata| metadata.modified()).ok())
+}
@@ -2,173 +2,302 @@
 
 extern crate chrono;
 extern crate clap;
-extern crate ctrlc;
 extern crate log;
 extern crate log4rs;
 extern crate postgres;
 extern crate serde_json;
 
 
-use std::fs::File;
-use std::io::Read;
+use std::path::Path;
 use std::process::exit;
-use std::sync::{Arc, mpsc};
-use std::sync::atomic::Ordering;
+use std::sync::{Arc, mpsc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
-use std::thread;
+use std::{thread, time};
 
 use clap::App;
-use serde::{Deserialize, Serialize};
-use log4rs::append::console::ConsoleAppender;
-use log4rs::append::rolling_file::RollingFileAppender;
-use log4rs::encode::pattern::PatternEncoder;
-use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
-use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
-use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
-use log4rs::config::{Config, Logger, Appender, Root};
-use log::LevelFilter;
-use std::path::Path;
+use openssl::ssl::SslAcceptor;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use crate::config::Configuration;
+use crate::shared::write_shared;
 
 pub mod record;
+pub mod units;
+mod check;
+mod config;
+mod logging;
+mod secrets;
+mod shared;
 mod socket;
 mod database;
+mod quarantine;
+mod deduplication;
+mod derived;
+mod metrics;
+mod http;
+mod health;
+mod journald;
+mod limits;
+mod systemd;
+
+/// Struct holding the handles to the parts of the running application that can be reconfigured.
+struct ReloadHandles
+{
+    /// Handle to replace the logging configuration.
+    log_handle: log4rs::Handle,
+    /// The verbosity given on the command line, applied to every logging configuration.
+    verbosity: i64,
+    /// The acceptor used by the socket thread for new connections.
+    tls_acceptor: Arc<RwLock<SslAcceptor>>,
+    /// The `allow` and `deny` lists checked by the socket thread for new connections.
+    socket_params: Arc<RwLock<socket::SocketParameters>>,
+    /// The parameters used by the database thread on the next reconnect.
+    database_parameters: Arc<RwLock<database::DatabaseParameters>>,
+    /// The parameters used by the database thread for the next record.
+    processing_parameters: Arc<RwLock<database::ProcessingParameters>>,
+}
+
+/// Function to reload the configuration and apply the changes to the running application.
+///
+/// The new configuration is validated completely before any change is applied, an invalid
+/// configuration leaves the running application untouched. This includes the database schema if
+/// the new configuration writes to other columns, e.g. after enabling derived metrics.
+///
+/// Changes to the following parameters are applied live:
+///
+/// * The logging configuration.
+///
+/// * The tls identity of the socket, used for new connections.
+///
+/// * The `allow` and `deny` lists of the socket, checked for new connections.
+///
+/// * The database connection parameters, used on the next reconnect.
+///
+/// * The deduplication and derived metrics parameters, used for the next record.
+///
+/// Changes to the socket address, the connection limits, timeouts and tcp keepalive, the
+/// quarantine and the http endpoint require a restart.
+///
+/// # Arguments
+///
+/// * `configuration_source` - The sources of the configuration.
+///
+/// * `configuration` - The running configuration, replaced on success.
+///   Parameters that require a restart keep their running values.
+///
+/// * `handles` - The handles to the reconfigurable parts of the application.
+///
+/// # Returns
+///
+/// * `Ok(())` - On success.
+///
+/// * `Err(...)` - If the new configuration cannot be loaded or is invalid.
+///
+fn reload_configuration(configuration_source: &config::ConfigurationSource, configuration: &mut Configuration, handles: &ReloadHandles) -> Result<(), String>
+{
+    let new_configuration = configuration_source.load()?;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-/// Struct representing the configuration of the application.
-pub struct Configuration {
-    /// Parameters for the database part ot the app.
-    database_connection_parameters: database::DatabaseParameters,
-    /// Parameters for the socket part of the app.
-    socket_connection_parameters: socket::TlsSocketParameters,
-    /// Logging folder location.
-    logging_folder: String,
+    let log_config = logging::load_log_config(&new_configuration, handles.verbosity)?;
+    let tls_acceptor = socket::build_tls_acceptor(&new_configuration.socket_connection_parameters)?;
+    database::build_tls_connector(&new_configuration.database_connection_parameters)?;
+
+    let running_socket_params = &configuration.socket_connection_parameters.socket_params;
+    let mut socket_params = new_configuration.socket_connection_parameters.socket_params.clone();
+    if socket_params.address != running_socket_params.address
+        || socket_params.port != running_socket_params.port
+        || new_configuration.socket_connection_parameters.systemd_socket_activation != configuration.socket_connection_parameters.systemd_socket_activation {
+        log::warn!(target: "dblogd", "Changed socket address requires a restart!");
+        socket_params.address = running_socket_params.address.clone();
+        socket_params.port = running_socket_params.port;
+    }
+    let running_socket_connection_parameters = &configuration.socket_connection_parameters;
+    if new_configuration.socket_connection_parameters.limits != running_socket_connection_parameters.limits
+        || new_configuration.socket_connection_parameters.handshake_timeout_secs != running_socket_connection_parameters.handshake_timeout_secs
+        || new_configuration.socket_connection_parameters.idle_timeout_secs != running_socket_connection_parameters.idle_timeout_secs
+        || new_configuration.socket_connection_parameters.tcp_keepalive != running_socket_connection_parameters.tcp_keepalive {
+        log::warn!(target: "dblogd", "Changed connection limits, timeouts and tcp keepalive require a restart!");
+    }
+    if new_configuration.quarantine_parameters != configuration.quarantine_parameters {
+        log::warn!(target: "dblogd", "Changed quarantine parameters require a restart!");
+    }
+    if new_configuration.http_parameters != configuration.http_parameters {
+        log::warn!(target: "dblogd", "Changed http parameters require a restart!");
+    }
+
+    // The schema is only checked if the new configuration writes to other columns, so a reload does
+    // not depend on the database otherwise.
+    let mut new_configuration = new_configuration;
+    new_configuration.quarantine_parameters = configuration.quarantine_parameters.clone();
+    if database::required_columns(&new_configuration) != database::required_columns(configuration) {
+        let mut database_client = match database::connect_database(&new_configuration.database_connection_parameters) {
+            Ok(database_client) => database_client,
+            Err(err) => {
+                return Err(format!("Cannot verify the database schema: {}", err));
+            }
+        };
+        database::verify_schema(&mut database_client, &new_configuration)?;
+    }
+
+    handles.log_handle.set_config(log_config);
+    write_shared(&handles.tls_acceptor, tls_acceptor);
+    write_shared(&handles.socket_params, socket_params.clone());
+    write_shared(&handles.database_parameters, new_configuration.database_connection_parameters.clone());
+    write_shared(&handles.processing_parameters, new_configuration.processing_parameters());
+
+    new_configuration.socket_connection_parameters.socket_params = socket_params;
+    new_configuration.socket_connection_parameters.systemd_socket_activation = configuration.socket_connection_parameters.systemd_socket_activation;
+    new_configuration.socket_connection_parameters.limits = configuration.socket_connection_parameters.limits.clone();
+    new_configuration.socket_connection_parameters.handshake_timeout_secs = configuration.socket_connection_parameters.handshake_timeout_secs;
+    new_configuration.socket_connection_parameters.idle_timeout_secs = configuration.socket_connection_parameters.idle_timeout_secs;
+    new_configuration.socket_connection_parameters.tcp_keepalive = configuration.socket_connection_parameters.tcp_keepalive.clone();
+    new_configuration.http_parameters = configuration.http_parameters.clone();
+    *configuration = new_configuration;
+    Ok(())
+}
+
+/// Function to send a notification to systemd, logging failures.
+///
+/// # Arguments
+///
+/// * `state` - The newline separated assignments to send, e.g. `READY=1`.
+///
+fn notify_systemd(state: &str)
+{
+    match systemd::notify(state) {
+        Ok(_) => {}
+        Err(err) => {
+            log::warn!(target: "dblogd", "Could not notify systemd: \'{}\'", err);
+        }
+    };
 }
 
 /// Main function of the application.
 ///
 /// It starts the database and socket threads.
+/// Once both are operational, readiness is reported to systemd, followed by periodic status
+/// updates and watchdog keepalives as long as both threads are alive.
+/// On `SIGHUP` the configuration is reloaded from all its sources, `SIGINT` and `SIGTERM` stop the application.
+/// The tls identity of the socket and the logging configuration file are reloaded whenever the
+/// files change.
 /// This function will await a close command from the user or run indefinitely.
 ///
 pub fn main() {
     let cli_yaml = clap::load_yaml!("cli.yml");
     let matches = App::from(cli_yaml).get_matches();
 
-    let config_file_path_str = match matches.value_of("config_file") {
-        Some(config_file_path) => config_file_path,
-        None => {
-            println!("Requried argument \'config\' not found!");
-            exit(104);
-        }
-    };
-    let config_file_path = Path::new(config_file_path_str);
-    let config_file_path_canon = match config_file_path.canonicalize() {
-        Ok(path) => path,
-        Err(err) => {
+    let config_file_path_canon = match matches.value_of("config_file").map(|path| Path::new(path).canonicalize()) {
+        Some(Ok(path)) => Some(path),
+        Some(Err(err)) => {
             println!("Cannot find the configuration path: \'{}\'", err);
             exit(105);
         }
+        None => None,
     };
-
-    let mut configuration_file = match File::open(config_file_path_canon) {
-        Ok(file) => file,
-        Err(err) => {
-            println!("Cannot open the configuration file: \'{}\'", err);
-            return;
-        }
+    let configuration_source = config::ConfigurationSource {
+        file: config_file_path_canon,
+        overrides: matches.values_of("set").map(|values| values.map(String::from).collect()).unwrap_or_default(),
     };
 
-    let mut configuration_string = String::new();
-    match configuration_file.read_to_string(&mut configuration_string) {
-        Ok(_) => {}
-        Err(err) => {
-            println!("Cannot read the configuration from file: \'{}\'", err);
-            return;
+    if let Some(check_matches) = matches.subcommand_matches("check-config") {
+        if check::check_configuration(&configuration_source, check_matches.is_present("database")) {
+            exit(0);
         }
-    };
+        exit(106);
+    }
 
-    let configuration = match serde_yaml::from_str::<Configuration>(configuration_string.as_str()) {
+    let (mut configuration, unknown_fields) = match configuration_source.load_with_unknown_fields() {
         Ok(res) => res,
         Err(err) => {
-            println!("Cannot deserialize the configuration: \'{}\'", err);
+            println!("{}", err);
             return;
         }
     };
 
-    let rolling_logger_file = format!("{}/dblogd.log", configuration.logging_folder);
-    let rolling_logger_file_pattern = format!("{}/dblogd.{}.log", configuration.logging_folder, "{}");
-
-    let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{d(%Y-%m-%d %H:%M:%S %Z)(utc)} - {h({l})} - {t} - {T} - {m}{n}"))).build();
-    let rolling_log_file = match RollingFileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{d(%Y-%m-%d %H:%M:%S %Z)(utc)} - {h({l})} - {t} - {T} - {m}{n}")))
-        .build(
-            rolling_logger_file.as_str(),
-            Box::new(CompoundPolicy::new(
-                Box::new(SizeTrigger::new(1000000)),
-                Box::new(FixedWindowRoller::builder()
-                    .base(1)
-                    .build(rolling_logger_file_pattern.as_str(), 5).unwrap()
-                    )
-                )
-            )
-        ) {
-        Ok(logger) => logger,
-        Err(err) => {
-            println!("Could not create rolling file logger: \'{}\'", err);
-            exit(101);
-        }
-    };
-
-    let log_config = match Config::builder()
-        .appender(Appender::builder().build("stdout", Box::new(stdout)))
-        .appender(Appender::builder().build("rolling_log_file", Box::new(rolling_log_file)))
-        .logger(Logger::builder()
-            .appenders(&[String::from("stdout"), String::from("rolling_log_file")])
-            .additive(false)
-            .build("dblogd::db", LevelFilter::Info))
-        .logger(Logger::builder()
-            .appenders(&[String::from("stdout"), String::from("rolling_log_file")])
-            .additive(false)
-            .build("dblogd::socket", LevelFilter::Info))
-        .logger(Logger::builder()
-            .appenders(&[String::from("stdout"), String::from("rolling_log_file")])
-            .additive(false)
-            .build("dblogd::socket::tls", LevelFilter::Info))
-        .logger(Logger::builder()
-            .appenders(&[String::from("stdout"), String::from("rolling_log_file")])
-            .additive(false)
-            .build("dblogd", LevelFilter::Info))
-        .build(Root::builder()
-            .appender("stdout")
-            .build(LevelFilter::Warn)) {
-        Ok(config) => config,
+    let verbosity = matches.occurrences_of("verbose") as i64 - matches.occurrences_of("quiet") as i64;
+    let (log_config, log_config_error) = match logging::load_log_config(&configuration, verbosity) {
+        Ok(config) => (config, None),
+        Err(err) if configuration.logging_config_file.is_some() => match logging::build_log_config(&configuration, verbosity) {
+            Ok(config) => (config, Some(err)),
+            Err(err) => {
+                println!("{}", err);
+                exit(102);
+            }
+        },
         Err(err) => {
             println!("{}", err);
             exit(102);
         }
     };
 
-    match log4rs::init_config(log_config) {
-        Ok(_) => {},
+    let log_handle = match log4rs::init_config(log_config) {
+        Ok(handle) => handle,
         Err(err) => {
             println!("Could not initialize logger: \'{}\'", err);
             exit(103);
         }
     };
 
-    let (tx, rx): (Sender<record::TemperatureRecord>, Receiver<record::TemperatureRecord>) = mpsc::channel();
-    let socket_tx_channel = tx.clone();
+    if let Some(err) = log_config_error {
+        log::warn!(target: "dblogd", "Using the built-in logging configuration: \'{}\'", err);
+    }
+    for unknown_field in unknown_fields {
+        log::warn!(target: "dblogd", "Ignoring unknown configuration field \'{}\'", unknown_field);
+    }
+
+    let tls_acceptor = match socket::build_tls_acceptor(&configuration.socket_connection_parameters) {
+        Ok(tls_acceptor) => Arc::new(RwLock::new(tls_acceptor)),
+        Err(err) => {
+            log::error!(target: "dblogd::socket", "{}", err);
+            exit(205);
+        }
+    };
+
+    // Connection errors are reported by the database thread, which also retries after a lost connection.
+    if let Ok(mut database_client) = database::connect_database(&configuration.database_connection_parameters) {
+        match database::verify_schema(&mut database_client, &configuration) {
+            Ok(_) => {}
+            Err(err) => {
+                log::error!(target: "dblogd::db", "{}", err);
+                exit(207);
+            }
+        };
+    }
+
+    // The socket thread holds the only sender, so the channel is closed once it finished.
+    let (socket_tx_channel, rx): (Sender<record::ReceivedRecord>, Receiver<record::ReceivedRecord>) = mpsc::channel();
+
+    let (rejected_tx, rejected_rx): (Sender<quarantine::RejectedRecord>, Receiver<quarantine::RejectedRecord>) = mpsc::channel();
+    let quarantine = match quarantine::Quarantine::open(&configuration.quarantine_parameters, rejected_tx) {
+        Ok(quarantine) => quarantine,
+        Err(err) => {
+            log::error!(target: "dblogd", "Cannot open the quarantine: \'{}\'", err);
+            exit(203);
+        }
+    };
+    let socket_quarantine = quarantine.clone();
+    let database_quarantine = quarantine;
+
+    let metrics = Arc::new(metrics::Metrics::new());
+    let socket_metrics = Arc::clone(&metrics);
+    let database_metrics = Arc::clone(&metrics);
 
-    let terminate_programm = Arc::new(std::sync::atomic::AtomicBool::new(false));
-    let terminate_main_thread = Arc::clone(&terminate_programm);
+    let terminate_programm = Arc::new(AtomicBool::new(false));
+    let terminate_signal_thread = Arc::clone(&terminate_programm);
     let terminate_socket_thread = Arc::clone(&terminate_programm);
     let terminate_database_thread = Arc::clone(&terminate_programm);
 
     let socket_configuration = configuration.socket_connection_parameters.clone();
+    let socket_tls_acceptor = Arc::clone(&tls_acceptor);
+    let socket_params = Arc::new(RwLock::new(configuration.socket_connection_parameters.socket_params.clone()));
+    let socket_access_lists = Arc::clone(&socket_params);
     let socket_thread = match thread::Builder::new()
         .name("socket".to_string())
         .spawn(move || {
-            socket::thread_tcp_listener_socket(socket_tx_channel, terminate_socket_thread, socket_configuration);
+            socket_metrics.socket_thread_alive.store(true, Ordering::SeqCst);
+            socket::thread_tcp_listener_socket(socket_tx_channel, socket_quarantine, Arc::clone(&socket_metrics), socket_tls_acceptor, socket_access_lists, terminate_socket_thread, socket_configuration);
+            socket_metrics.socket_thread_alive.store(false, Ordering::SeqCst);
         }) {
         Ok(socket_handle) => socket_handle,
         Err(err) => {
@@ -177,11 +306,16 @@ pub fn main() {
         }
     };
 
-    let database_configuration = configuration.database_connection_parameters.clone();
+    let database_parameters = Arc::new(RwLock::new(configuration.database_connection_parameters.clone()));
+    let processing_parameters = Arc::new(RwLock::new(configuration.processing_parameters()));
+    let database_configuration = Arc::clone(&database_parameters);
+    let processing_configuration = Arc::clone(&processing_parameters);
     let database_thread = match thread::Builder::new()
         .name("database".to_string())
         .spawn(move || {
-            database::database_thread(rx, terminate_database_thread, database_configuration);
+            database_metrics.database_thread_alive.store(true, Ordering::SeqCst);
+            database::database_thread(rx, rejected_rx, database_quarantine, processing_configuration, Arc::clone(&database_metrics), terminate_database_thread, database_configuration);
+            database_metrics.database_thread_alive.store(false, Ordering::SeqCst);
         }) {
         Ok(socket_handle) => socket_handle,
         Err(err) => {
@@ -190,10 +324,154 @@ pub fn main() {
         }
     };
 
-    ctrlc::set_handler(move || {
-        log::info!(target: "dblogd","Termination signal received!");
-        terminate_main_thread.store(true, Ordering::SeqCst);
-    }).expect("Error setting Ctrl-C handler");
+    let http_thread = match configuration.http_parameters.clone() {
+        Some(http_configuration) => {
+            let terminate_http_thread = Arc::clone(&terminate_programm);
+            let http_metrics = Arc::clone(&metrics);
+            match thread::Builder::new()
+                .name("http".to_string())
+                .spawn(move || {
+                    http::thread_http_server(http_metrics, terminate_http_thread, http_configuration);
+                }) {
+                Ok(http_handle) => Some(http_handle),
+                Err(err) => {
+                    log::error!(target: "dblogd", "Cannot start the http thread: \'{}\'", err);
+                    exit(204);
+                }
+            }
+        }
+        None => None,
+    };
+
+    let reload_handles = ReloadHandles {
+        log_handle,
+        verbosity,
+        tls_acceptor,
+        socket_params,
+        database_parameters,
+        processing_parameters,
+    };
+
+    let reload_requested = Arc::new(AtomicBool::new(false));
+    let reload_signal_thread = Arc::clone(&reload_requested);
+    let mut signals = match Signals::new([SIGINT, SIGTERM, SIGHUP]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            log::error!(target: "dblogd", "Cannot register the signal handlers: \'{}\'", err);
+            exit(206);
+        }
+    };
+    match thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            for signal in signals.forever() {
+                if signal == SIGHUP {
+                    log::info!(target: "dblogd", "Reload signal received!");
+                    reload_signal_thread.store(true, Ordering::SeqCst);
+                } else {
+                    log::info!(target: "dblogd", "Termination signal received!");
+                    terminate_signal_thread.store(true, Ordering::SeqCst);
+                }
+            }
+        }) {
+        Ok(_) => {}
+        Err(err) => {
+            log::error!(target: "dblogd", "Cannot start the signal thread: \'{}\'", err);
+            exit(206);
+        }
+    };
+
+    let watchdog_interval = systemd::watchdog_interval();
+    let status_interval = time::Duration::from_secs(10);
+    let mut ready_notified = false;
+    let mut last_watchdog = time::Instant::now();
+    let mut last_status = time::Instant::now();
+    let mut last_identity_check = time::Instant::now();
+    let mut identity_modified = socket::identity_modified(&configuration.socket_connection_parameters);
+    let mut last_log_config_check = time::Instant::now();
+    let mut log_config_modified = logging::log_config_file_modified(&configuration);
+
+    while !terminate_programm.load(Ordering::SeqCst) {
+        if reload_requested.swap(false, Ordering::SeqCst) {
+            // Before the first readiness the service is still starting and a reload does not change that.
+            if ready_notified {
+                notify_systemd("RELOADING=1");
+            }
+            match reload_configuration(&configuration_source, &mut configuration, &reload_handles) {
+                Ok(_) => log::info!(target: "dblogd", "Configuration reloaded!"),
+                Err(err) => {
+                    log::error!(target: "dblogd", "Configuration not reloaded, keeping the old configuration: \'{}\'", err);
+                }
+            };
+            identity_modified = socket::identity_modified(&configuration.socket_connection_parameters);
+            log_config_modified = logging::log_config_file_modified(&configuration);
+            if ready_notified {
+                notify_systemd("READY=1");
+            }
+        }
+
+        let log_config_refresh = configuration.logging_config_refresh_secs;
+        if configuration.logging_config_file.is_some() && log_config_refresh > 0
+            && last_log_config_check.elapsed() >= time::Duration::from_secs(log_config_refresh) {
+            last_log_config_check = time::Instant::now();
+            let modified = logging::log_config_file_modified(&configuration);
+            if modified != log_config_modified {
+                match logging::load_log_config(&configuration, verbosity) {
+                    Ok(log_config) => {
+                        reload_handles.log_handle.set_config(log_config);
+                        log_config_modified = modified;
+                        log::info!(target: "dblogd", "Logging configuration file changed, reloaded the logging configuration!");
+                    }
+                    Err(err) => {
+                        log::error!(target: "dblogd", "Logging configuration file changed but could not be loaded, keeping the old configuration: \'{}\'", err);
+                    }
+                };
+            }
+        }
+
+        let identity_watch_interval = configuration.socket_connection_parameters.identity_watch_interval_secs;
+        if identity_watch_interval > 0 && last_identity_check.elapsed() >= time::Duration::from_secs(identity_watch_interval) {
+            last_identity_check = time::Instant::now();
+            let modified = socket::identity_modified(&configuration.socket_connection_parameters);
+            if modified != identity_modified {
+                match socket::build_tls_acceptor(&configuration.socket_connection_parameters) {
+                    Ok(tls_acceptor) => {
+                        write_shared(&reload_handles.tls_acceptor, tls_acceptor);
+                        identity_modified = modified;
+                        log::info!(target: "dblogd::socket", "Tls identity changed, new connections use the new identity!");
+                    }
+                    Err(err) => {
+                        log::error!(target: "dblogd::socket", "Tls identity changed but could not be loaded, keeping the old identity: \'{}\'", err);
+                    }
+                };
+            }
+        }
+
+        if !ready_notified
+            && metrics.listener_bound.load(Ordering::SeqCst)
+            && metrics.database_connected.load(Ordering::SeqCst) {
+            notify_systemd("READY=1");
+            ready_notified = true;
+            log::info!(target: "dblogd", "Ready");
+        }
+
+        if let Some(watchdog_interval) = watchdog_interval {
+            if last_watchdog.elapsed() >= watchdog_interval
+                && metrics.socket_thread_alive.load(Ordering::SeqCst)
+                && metrics.database_thread_alive.load(Ordering::SeqCst) {
+                notify_systemd("WATCHDOG=1");
+                last_watchdog = time::Instant::now();
+            }
+        }
+
+        if ready_notified && last_status.elapsed() >= status_interval {
+            notify_systemd(format!("STATUS={}", metrics.summary()).as_str());
+            last_status = time::Instant::now();
+        }
+
+        thread::sleep(time::Duration::from_millis(100));
+    }
+    notify_systemd("STOPPING=1");
 
     match socket_thread.join() {
         Ok(_) => log::debug!(target: "dblogd", "Joined socket thread!"),
@@ -210,6 +488,16 @@ pub fn main() {
         }
     };
 
+    if let Some(http_thread) = http_thread {
+        match http_thread.join() {
+            Ok(_) => log::debug!(target: "dblogd", "Joined http thread!"),
+            Err(_) => {
+                log::error!(target: "dblogd", "Could not join the http thread!");
+                exit(301);
+            }
+        };
+    }
+
     log::info!(target: "dblogd", "Exiting");
     exit(0);
 }
@@ -0,0 +1,356 @@
+//! Module that collects the ingest and database metrics of the application.
+//!
+//! The metrics are updated by the socket and database threads and rendered in the Prometheus
+//! text exposition format by the http thread.
+//!
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::time;
+
+use chrono::Utc;
+
+/// Upper bounds of the insert latency histogram buckets in seconds.
+const INSERT_LATENCY_BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Number of sensors whose last seen time is kept before the least recently seen is discarded.
+const MAX_SENSOR_LAST_SEEN: usize = 1024;
+
+/// Struct representing a histogram with fixed buckets.
+struct Histogram
+{
+    /// Upper bounds of the buckets in seconds.
+    bounds: &'static [f64],
+    /// Number of observations per bucket, not cumulative.
+    buckets: Vec<AtomicU64>,
+    /// Sum of all observations in microseconds.
+    sum_micros: AtomicU64,
+    /// Number of observations.
+    count: AtomicU64,
+}
+
+impl Histogram {
+    /// Creates a new histogram without observations.
+    ///
+    /// # Arguments
+    ///
+    /// * `bounds` - Upper bounds of the buckets in seconds, in ascending order.
+    ///
+    fn new(bounds: &'static [f64]) -> Histogram
+    {
+        Histogram {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds an observation to the first bucket whose bound it does not exceed.
+    ///
+    /// Observations above the largest bound are only counted in `+Inf`, the sum and the count.
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - The observed duration.
+    ///
+    fn observe(&self, duration: time::Duration)
+    {
+        let seconds = duration.as_secs_f64();
+        if let Some(index) = self.bounds.iter().position(|bound| seconds <= *bound) {
+            self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders the histogram in the Prometheus text exposition format with cumulative buckets.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - The output the histogram is appended to.
+    ///
+    /// * `name` - The name of the metric.
+    ///
+    /// * `help` - The description of the metric.
+    ///
+    fn render(&self, out: &mut String, name: &str, help: &str)
+    {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(self.buckets.iter()) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+/// Struct holding all metrics of the application.
+///
+/// A single instance is shared between all threads.
+pub struct Metrics
+{
+    /// Whether the socket thread is running.
+    pub socket_thread_alive: AtomicBool,
+    /// Whether the database thread is running.
+    pub database_thread_alive: AtomicBool,
+    /// Whether the tcp listener is bound and accepting connections.
+    pub listener_bound: AtomicBool,
+    /// Whether the database connection is established.
+    pub database_connected: AtomicBool,
+    /// Number of accepted tcp connections.
+    pub connections_accepted: AtomicU64,
+    /// Number of currently open tls connections.
+    pub connections_active: AtomicI64,
+    /// Number of failed tls handshakes.
+    pub tls_handshake_failures: AtomicU64,
+    /// Number of tls handshakes aborted after the handshake timeout.
+    pub tls_handshake_timeouts: AtomicU64,
+    /// Number of connections closed after the idle timeout.
+    pub idle_timeouts: AtomicU64,
+    /// Number of bytes received from all peers.
+    pub bytes_received: AtomicU64,
+    /// Number of records received and passed to the database thread.
+    pub records_received: AtomicU64,
+    /// Number of payloads that were not valid UTF-8.
+    pub utf8_decode_errors: AtomicU64,
+    /// Number of payloads that could not be deserialized.
+    pub json_decode_errors: AtomicU64,
+    /// Number of records waiting in the channel to the database thread.
+    pub queue_depth: AtomicI64,
+    /// Number of records inserted into the database.
+    pub records_inserted: AtomicU64,
+    /// Number of records skipped as duplicates.
+    pub records_duplicate: AtomicU64,
+    /// Number of reestablished database connections.
+    pub database_reconnects: AtomicU64,
+    /// Latency of the record inserts.
+    insert_latency: Histogram,
+    /// Number of failed inserts by reason.
+    insert_failures: Mutex<HashMap<&'static str, u64>>,
+    /// Number of connections closed after accept by the exceeded limit.
+    connections_rejected: Mutex<HashMap<&'static str, u64>>,
+    /// Number of records exceeding a rate limit by the scope of the limit.
+    records_rate_limited: Mutex<HashMap<&'static str, u64>>,
+    /// Unix timestamp a record was last received for each sensor.
+    sensor_last_seen: Mutex<HashMap<String, i64>>,
+}
+
+impl Metrics {
+    /// Creates a new set of metrics with all values set to zero.
+    pub fn new() -> Metrics
+    {
+        Metrics {
+            socket_thread_alive: AtomicBool::new(false),
+            database_thread_alive: AtomicBool::new(false),
+            listener_bound: AtomicBool::new(false),
+            database_connected: AtomicBool::new(false),
+            connections_accepted: AtomicU64::new(0),
+            connections_active: AtomicI64::new(0),
+            tls_handshake_failures: AtomicU64::new(0),
+            tls_handshake_timeouts: AtomicU64::new(0),
+            idle_timeouts: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            records_received: AtomicU64::new(0),
+            utf8_decode_errors: AtomicU64::new(0),
+            json_decode_errors: AtomicU64::new(0),
+            queue_depth: AtomicI64::new(0),
+            records_inserted: AtomicU64::new(0),
+            records_duplicate: AtomicU64::new(0),
+            database_reconnects: AtomicU64::new(0),
+            insert_latency: Histogram::new(&INSERT_LATENCY_BUCKETS),
+            insert_failures: Mutex::new(HashMap::new()),
+            connections_rejected: Mutex::new(HashMap::new()),
+            records_rate_limited: Mutex::new(HashMap::new()),
+            sensor_last_seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records the latency of a single insert.
+    pub fn observe_insert_latency(&self, duration: time::Duration)
+    {
+        self.insert_latency.observe(duration);
+    }
+
+    /// Counts a failed insert.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The reason the insert failed, e.g. the rejection stage.
+    ///
+    pub fn insert_failed(&self, reason: &'static str)
+    {
+        increment_labeled_counter(&self.insert_failures, reason);
+    }
+
+    /// Counts a connection closed after accept.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The exceeded limit, e.g. `max_connections`.
+    ///
+    pub fn connection_rejected(&self, reason: &'static str)
+    {
+        increment_labeled_counter(&self.connections_rejected, reason);
+    }
+
+    /// Counts a record exceeding a rate limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `scope` - The scope of the exceeded limit, `connection` or `sensor`.
+    ///
+    pub fn record_rate_limited(&self, scope: &'static str)
+    {
+        increment_labeled_counter(&self.records_rate_limited, scope);
+    }
+
+    /// Marks a sensor as seen at the current time.
+    ///
+    /// Only sensors known to the database should be marked, as every sensor becomes a label of the
+    /// rendered metrics. At most `MAX_SENSOR_LAST_SEEN` sensors are kept.
+    pub fn sensor_seen(&self, sensor_name: &str)
+    {
+        let mut sensor_last_seen = match self.sensor_last_seen.lock() {
+            Ok(sensor_last_seen) => sensor_last_seen,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if sensor_last_seen.len() >= MAX_SENSOR_LAST_SEEN && !sensor_last_seen.contains_key(sensor_name) {
+            let least_recently_seen = sensor_last_seen.iter()
+                .min_by_key(|(_, timestamp)| **timestamp)
+                .map(|(sensor_name, _)| sensor_name.clone());
+            if let Some(least_recently_seen) = least_recently_seen {
+                sensor_last_seen.remove(&least_recently_seen);
+            }
+        }
+        sensor_last_seen.insert(String::from(sensor_name), Utc::now().timestamp());
+    }
+
+    /// Returns a single line summary of the most important metrics.
+    pub fn summary(&self) -> String
+    {
+        format!("{} connections active, {} records received, {} inserted, {} queued",
+                self.connections_active.load(Ordering::Relaxed),
+                self.records_received.load(Ordering::Relaxed),
+                self.records_inserted.load(Ordering::Relaxed),
+                self.queue_depth.load(Ordering::Relaxed))
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String
+    {
+        let mut out = String::new();
+
+        render_gauge(&mut out, "dblogd_listener_up", "Whether the tcp listener is bound.",
+                     self.listener_bound.load(Ordering::Relaxed) as i64);
+        render_gauge(&mut out, "dblogd_database_up", "Whether the database connection is established.",
+                     self.database_connected.load(Ordering::Relaxed) as i64);
+
+        render_counter(&mut out, "dblogd_connections_accepted_total", "Number of accepted tcp connections.",
+                       self.connections_accepted.load(Ordering::Relaxed));
+        render_gauge(&mut out, "dblogd_connections_active", "Number of currently open tls connections.",
+                     self.connections_active.load(Ordering::Relaxed));
+        render_counter(&mut out, "dblogd_tls_handshake_failures_total", "Number of failed tls handshakes.",
+                       self.tls_handshake_failures.load(Ordering::Relaxed));
+        render_counter(&mut out, "dblogd_tls_handshake_timeouts_total", "Number of tls handshakes aborted after the handshake timeout.",
+                       self.tls_handshake_timeouts.load(Ordering::Relaxed));
+        render_counter(&mut out, "dblogd_idle_timeouts_total", "Number of connections closed after the idle timeout.",
+                       self.idle_timeouts.load(Ordering::Relaxed));
+        render_counter(&mut out, "dblogd_received_bytes_total", "Number of bytes received from all peers.",
+                       self.bytes_received.load(Ordering::Relaxed));
+        render_counter(&mut out, "dblogd_received_records_total", "Number of records received.",
+                       self.records_received.load(Ordering::Relaxed));
+        render_counter(&mut out, "dblogd_utf8_decode_errors_total", "Number of payloads that were not valid UTF-8.",
+                       self.utf8_decode_errors.load(Ordering::Relaxed));
+        render_counter(&mut out, "dblogd_json_decode_errors_total", "Number of payloads that could not be deserialized.",
+                       self.json_decode_errors.load(Ordering::Relaxed));
+        render_gauge(&mut out, "dblogd_queue_depth", "Number of records waiting for the database thread.",
+                     self.queue_depth.load(Ordering::Relaxed));
+        render_counter(&mut out, "dblogd_inserted_records_total", "Number of records inserted into the database.",
+                       self.records_inserted.load(Ordering::Relaxed));
+        render_counter(&mut out, "dblogd_duplicate_records_total", "Number of records skipped as duplicates.",
+                       self.records_duplicate.load(Ordering::Relaxed));
+        render_counter(&mut out, "dblogd_database_reconnects_total", "Number of reestablished database connections.",
+                       self.database_reconnects.load(Ordering::Relaxed));
+
+        self.insert_latency.render(&mut out, "dblogd_insert_duration_seconds", "Latency of the record inserts.");
+
+        render_labeled_counter(&mut out, "dblogd_insert_failures_total", "Number of failed inserts by reason.",
+                               "reason", &self.insert_failures);
+        render_labeled_counter(&mut out, "dblogd_rejected_connections_total", "Number of connections closed after accept by reason.",
+                               "reason", &self.connections_rejected);
+        render_labeled_counter(&mut out, "dblogd_rate_limited_records_total", "Number of records exceeding a rate limit by scope.",
+                               "scope", &self.records_rate_limited);
+
+        let _ = writeln!(out, "# HELP dblogd_sensor_last_seen_timestamp_seconds Unix time a record was last received from the sensor.");
+        let _ = writeln!(out, "# TYPE dblogd_sensor_last_seen_timestamp_seconds gauge");
+        {
+            let sensor_last_seen = match self.sensor_last_seen.lock() {
+                Ok(sensor_last_seen) => sensor_last_seen,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let mut sensors: Vec<_> = sensor_last_seen.iter().collect();
+            sensors.sort();
+            for (sensor_name, timestamp) in sensors {
+                let _ = writeln!(out, "dblogd_sensor_last_seen_timestamp_seconds{{sensor=\"{}\"}} {}",
+                                 escape_label_value(sensor_name), timestamp);
+            }
+        }
+
+        out
+    }
+}
+
+/// Function to write a counter in the Prometheus text exposition format.
+fn render_counter(out: &mut String, name: &str, help: &str, value: u64)
+{
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Function to increment a counter with a label.
+fn increment_labeled_counter(counter: &Mutex<HashMap<&'static str, u64>>, label_value: &'static str)
+{
+    let mut counter = match counter.lock() {
+        Ok(counter) => counter,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    *counter.entry(label_value).or_insert(0) += 1;
+}
+
+/// Function to write a counter with a label in the Prometheus text exposition format.
+fn render_labeled_counter(out: &mut String, name: &str, help: &str, label: &str, counter: &Mutex<HashMap<&'static str, u64>>)
+{
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let counter = match counter.lock() {
+        Ok(counter) => counter,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let mut label_values: Vec<_> = counter.iter().collect();
+    label_values.sort();
+    for (label_value, count) in label_values {
+        let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, label_value, count);
+    }
+}
+
+/// Function to write a gauge in the Prometheus text exposition format.
+fn render_gauge(out: &mut String, name: &str, help: &str, value: i64)
+{
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Function to escape a label value for the Prometheus text exposition format.
+fn escape_label_value(value: &str) -> String
+{
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
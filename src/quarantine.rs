@@ -0,0 +1,210 @@
+//! Module for quarantining payloads that could not be turned into database rows.
+//!
+//! Rejected payloads are stored together with the peer address, the time they were received,
+//! the stage of the failure and the error text, so they can be inspected and re-submitted later.
+//! Payloads of the `decode` stage are not valid UTF-8 and are stored base64 encoded, so they can be
+//! replayed exactly.
+//!
+//! Two sinks are supported:
+//!
+//! * `file` - Appends one JSON object per line to a file.
+//!
+//! * `database` - Inserts the rejected payloads into the `public.rejected_records` table
+//!   (see `resources/sql/001_rejected_records.sql`).
+//!
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "sink", rename_all = "snake_case")]
+/// Enum representing the configured sink for rejected payloads.
+pub enum QuarantineParameters
+{
+    /// Append rejected payloads as JSON lines to a file.
+    File {
+        /// The location of the JSONL file.
+        path: String,
+    },
+    /// Insert rejected payloads into the `public.rejected_records` table.
+    Database,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Enum representing the stage of the pipeline a payload was rejected in.
+pub enum RejectionStage
+{
+    /// The payload was not valid UTF-8, it is stored base64 encoded.
+    Decode,
+    /// The payload could not be deserialized into a record.
+    Deserialize,
+    /// The sensor of the record is not known to the database.
+    Sensor,
+    /// The record could not be inserted into the database.
+    Insert,
+    /// The record was still queued when the shutdown deadline passed.
+    Shutdown,
+}
+
+impl RejectionStage {
+    /// Returns the name of the stage as stored in the quarantine.
+    pub fn as_str(self) -> &'static str
+    {
+        match self {
+            RejectionStage::Decode => "decode",
+            RejectionStage::Deserialize => "deserialize",
+            RejectionStage::Sensor => "sensor",
+            RejectionStage::Insert => "insert",
+            RejectionStage::Shutdown => "shutdown",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Struct representing a single rejected payload.
+pub struct RejectedRecord
+{
+    /// The raw payload as received from the peer, base64 encoded for the `decode` stage.
+    pub payload: String,
+    /// The address of the peer that sent the payload.
+    pub peer_addr: Option<String>,
+    /// Time the payload was received.
+    pub received_at: chrono::DateTime<Utc>,
+    /// The stage the payload was rejected in.
+    pub stage: RejectionStage,
+    /// The error that caused the rejection.
+    pub error: String,
+}
+
+impl RejectedRecord {
+    /// Creates a new rejected record.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The raw payload as received from the peer, base64 encoded for the `decode` stage.
+    ///
+    /// * `peer_addr` - The address of the peer, if known.
+    ///
+    /// * `received_at` - Time the payload was received.
+    ///
+    /// * `stage` - The stage the payload was rejected in.
+    ///
+    /// * `error` - The error that caused the rejection.
+    ///
+    pub fn new(payload: String,
+               peer_addr: Option<SocketAddr>,
+               received_at: chrono::DateTime<Utc>,
+               stage: RejectionStage,
+               error: String) -> RejectedRecord
+    {
+        RejectedRecord {
+            payload,
+            peer_addr: peer_addr.map(|addr| addr.to_string()),
+            received_at,
+            stage,
+            error,
+        }
+    }
+}
+
+#[derive(Clone)]
+/// Enum representing an opened quarantine sink.
+enum QuarantineSink
+{
+    /// Rejected payloads are only logged.
+    Disabled,
+    /// Rejected payloads are appended to a file.
+    File(Arc<Mutex<File>>),
+    /// Rejected payloads are passed to the database thread.
+    Database(Sender<RejectedRecord>),
+}
+
+#[derive(Clone)]
+/// Handle to the quarantine shared between the socket and the database thread.
+pub struct Quarantine
+{
+    sink: QuarantineSink,
+}
+
+impl Quarantine {
+    /// Opens the quarantine sink described by the parameters.
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - The configured sink or `None` if the quarantine is disabled.
+    ///
+    /// * `database_tx` - Sender to pass rejected payloads to the database thread.
+    ///   Only used by the `database` sink.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Quarantine)` - On success.
+    ///
+    /// * `Err(...)` - If the quarantine file cannot be opened.
+    ///
+    pub fn open(params: &Option<QuarantineParameters>, database_tx: Sender<RejectedRecord>) -> Result<Quarantine, String>
+    {
+        let sink = match params {
+            None => QuarantineSink::Disabled,
+            Some(QuarantineParameters::File { path }) => {
+                match OpenOptions::new().create(true).append(true).open(path) {
+                    Ok(file) => QuarantineSink::File(Arc::new(Mutex::new(file))),
+                    Err(err) => {
+                        return Err(format!("Could not open quarantine file \'{}\': \'{}\'", path, err));
+                    }
+                }
+            }
+            Some(QuarantineParameters::Database) => QuarantineSink::Database(database_tx),
+        };
+        Ok(Quarantine { sink })
+    }
+
+    /// Stores a rejected payload in the quarantine.
+    ///
+    /// Failures to store the payload are logged and otherwise ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `rejected_record` - The rejected payload.
+    ///
+    pub fn reject(&self, rejected_record: RejectedRecord)
+    {
+        match &self.sink {
+            QuarantineSink::Disabled => {}
+            QuarantineSink::File(file) => {
+                let mut line = match serde_json::to_string(&rejected_record) {
+                    Ok(line) => line,
+                    Err(err) => {
+                        log::error!(target: "dblogd::quarantine", "Could not serialize rejected record: \'{}\'", err);
+                        return;
+                    }
+                };
+                line.push('\n');
+                let mut file = match file.lock() {
+                    Ok(file) => file,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                match file.write_all(line.as_bytes()) {
+                    Ok(_) => log::debug!(target: "dblogd::quarantine", "Quarantined rejected record!"),
+                    Err(err) => {
+                        log::error!(target: "dblogd::quarantine", "Could not write rejected record to quarantine file: \'{}\'", err);
+                    }
+                };
+            }
+            QuarantineSink::Database(tx) => {
+                match tx.send(rejected_record) {
+                    Ok(_) => log::debug!(target: "dblogd::quarantine", "Send rejected record to database thread!"),
+                    Err(err) => {
+                        log::error!(target: "dblogd::quarantine", "Could not send rejected record to database thread: \'{}\'", err);
+                    }
+                };
+            }
+        }
+    }
+}
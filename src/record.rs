@@ -1,7 +1,11 @@
 //! Module that contains all valid record types for this application.
+use std::net::SocketAddr;
+
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
+use crate::units::{HumidityUnit, PressureUnit, TemperatureUnit};
+
 #[derive(Serialize, Deserialize, Debug)]
 /// Struct representing a temperature and humidity record.
 pub struct TemperatureRecord
@@ -10,8 +14,64 @@ pub struct TemperatureRecord
     pub timestamp: chrono::DateTime<Utc>,
     /// The name of the sensor that recorded the record.
     pub sensor_name: String,
-    /// Temperature value in celsius.
-    pub celsius: f64,
-    /// Relative humidity value.
+    /// Temperature value in the `temperature_unit`.
+    #[serde(alias = "celsius")]
+    pub temperature: f64,
+    /// Unit of the temperature value, celsius if not set.
+    #[serde(default)]
+    pub temperature_unit: TemperatureUnit,
+    /// Relative humidity value in the `humidity_unit`.
     pub humidity: f64,
-}
\ No newline at end of file
+    /// Unit of the relative humidity value, percent if not set.
+    #[serde(default)]
+    pub humidity_unit: HumidityUnit,
+    /// Optional pressure value in the `pressure_unit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<f64>,
+    /// Unit of the pressure value, hectopascal if not set.
+    #[serde(default)]
+    pub pressure_unit: PressureUnit,
+    /// Optional sequence number assigned by the device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// Optional unique message id assigned by the device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// Optional metadata of the device, e.g. firmware version, RSSI, location or battery state.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl TemperatureRecord {
+    /// Returns the temperature normalized to degree celsius.
+    pub fn temperature_celsius(&self) -> f64
+    {
+        self.temperature_unit.to_canonical(self.temperature)
+    }
+
+    /// Returns the relative humidity normalized to percent.
+    pub fn humidity_percent(&self) -> f64
+    {
+        self.humidity_unit.to_canonical(self.humidity)
+    }
+
+    /// Returns the pressure normalized to pascal.
+    pub fn pressure_pascal(&self) -> Option<f64>
+    {
+        self.pressure.map(|pressure| self.pressure_unit.to_canonical(pressure))
+    }
+}
+
+#[derive(Debug)]
+/// Struct representing a record together with the metadata of its reception.
+pub struct ReceivedRecord
+{
+    /// The record parsed from the payload.
+    pub record: TemperatureRecord,
+    /// The raw payload the record was parsed from.
+    pub payload: String,
+    /// The address of the peer that sent the record.
+    pub peer_addr: Option<SocketAddr>,
+    /// Time the payload was received.
+    pub received_at: chrono::DateTime<Utc>,
+}
@@ -0,0 +1,148 @@
+//! Module to resolve secrets referenced by the configuration.
+//!
+//! Every secret field of the configuration accepts one of the following:
+//!
+//! * A plain value, used as it is.
+//!
+//! * A value of exactly `${NAME}`, replaced by the environment variable `NAME`.
+//!
+//! * A file in the matching `*_file` field, whose content is used as the secret.
+//!   Relative paths are resolved against `$CREDENTIALS_DIRECTORY`, so credentials passed with
+//!   `LoadCredential=` in the systemd unit can be referenced by their name.
+//!
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Function to replace an environment variable reference by the value of the variable.
+///
+/// Only a value consisting of exactly one reference, e.g. `${DB_PASSWORD}`, is replaced. Any
+/// other value is used as it is, so existing plain values containing `$` keep working.
+///
+/// # Arguments
+///
+/// * `value` - The value, possibly a `${NAME}` reference.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The value of the variable if `value` is a reference, otherwise `value`.
+///
+/// * `Err(...)` - If the referenced variable is not set.
+///
+pub fn expand_env(value: &str) -> Result<String, String>
+{
+    let name = match value.strip_prefix("${").and_then(|rest| rest.strip_suffix('}')) {
+        Some(name) if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => name,
+        _ => return Ok(String::from(value)),
+    };
+
+    match env::var(name) {
+        Ok(variable) => Ok(variable),
+        Err(err) => Err(format!("Cannot read environment variable \'{}\': \'{}\'", name, err)),
+    }
+}
+
+/// Function to resolve the location of a secret file.
+///
+/// # Arguments
+///
+/// * `path` - The configured location of the file.
+///
+/// # Returns
+///
+/// The location of the file, relative paths are resolved against `$CREDENTIALS_DIRECTORY` if set.
+///
+fn secret_file_path(path: &str) -> PathBuf
+{
+    let path = Path::new(path);
+    match env::var_os("CREDENTIALS_DIRECTORY") {
+        Some(credentials_directory) if path.is_relative() => Path::new(&credentials_directory).join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Function to read a secret from a file.
+///
+/// A single trailing line break is removed from the content.
+///
+/// # Arguments
+///
+/// * `path` - The location of the file, see `secret_file_path`.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The secret on success.
+///
+/// * `Err(...)` - If the file cannot be read or is not valid UTF-8.
+///
+pub fn read_secret_file(path: &str) -> Result<String, String>
+{
+    let secret_path = secret_file_path(path);
+    let mut secret = match fs::read_to_string(&secret_path) {
+        Ok(secret) => secret,
+        Err(err) => {
+            return Err(format!("Cannot read secret file \'{}\': \'{}\'", secret_path.display(), err));
+        }
+    };
+
+    if secret.ends_with('\n') {
+        secret.pop();
+        if secret.ends_with('\r') {
+            secret.pop();
+        }
+    }
+    Ok(secret)
+}
+
+/// Function to resolve a secret field of the configuration.
+///
+/// # Arguments
+///
+/// * `value` - The configured value of the field, if any.
+///
+/// * `file` - The configured `*_file` field, taking precedence over the value.
+///
+/// # Returns
+///
+/// * `Ok(Option<String>)` - The resolved secret, or `None` if neither is configured.
+///
+/// * `Err(...)` - If the file cannot be read or an environment variable is not set.
+///
+pub fn resolve(value: Option<&str>, file: Option<&str>) -> Result<Option<String>, String>
+{
+    match (value, file) {
+        (_, Some(file)) => read_secret_file(file).map(Some),
+        (Some(value), None) => expand_env(value).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn expand_env_replaces_whole_reference()
+    {
+        env::set_var("SECRETS_TEST_SECRET", "s3cret");
+        assert_eq!(expand_env("${SECRETS_TEST_SECRET}"), Ok(String::from("s3cret")));
+    }
+
+    #[test]
+    fn expand_env_keeps_other_values()
+    {
+        env::set_var("SECRETS_TEST_PARTIAL", "unused");
+        assert_eq!(expand_env("pa$$word"), Ok(String::from("pa$$word")));
+        assert_eq!(expand_env("prefix${SECRETS_TEST_PARTIAL}"), Ok(String::from("prefix${SECRETS_TEST_PARTIAL}")));
+        assert_eq!(expand_env("${SECRETS_TEST_PARTIAL"), Ok(String::from("${SECRETS_TEST_PARTIAL")));
+        assert_eq!(expand_env("${}"), Ok(String::from("${}")));
+        assert_eq!(expand_env("${A B}"), Ok(String::from("${A B}")));
+    }
+
+    #[test]
+    fn expand_env_fails_for_unset_variable()
+    {
+        assert!(expand_env("${SECRETS_TEST_UNSET}").is_err());
+    }
+}
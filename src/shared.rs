@@ -0,0 +1,24 @@
+//! Module to access the parameters shared between the main thread and the worker threads.
+//!
+//! Shared parameters are held in a `RwLock` and only ever replaced as a whole, so a lock poisoned
+//! by a panicking thread still holds consistent parameters and is used as it is.
+//!
+use std::sync::RwLock;
+
+/// Function to read a copy of shared parameters.
+pub fn read_shared<T: Clone>(shared: &RwLock<T>) -> T
+{
+    match shared.read() {
+        Ok(value) => value.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    }
+}
+
+/// Function to replace shared parameters.
+pub fn write_shared<T>(shared: &RwLock<T>, value: T)
+{
+    match shared.write() {
+        Ok(mut guard) => *guard = value,
+        Err(poisoned) => *poisoned.into_inner() = value,
+    };
+}
@@ -2,21 +2,44 @@
 //! Module to manage a TCP/TLS socket that passes valid json TemperatureRecords payloads from the
 //! socket to the database thread.
 //!
-use std::{io, time};
+use std::{fs, io, thread, time};
 use std::fs::File;
 use std::io::Read;
-use std::net::{TcpListener, TcpStream};
-use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
 
-use native_tls::{HandshakeError, Identity, MidHandshakeTlsStream, Protocol, TlsAcceptor, TlsStream};
+use chrono::Utc;
+use ipnet::IpNet;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{AlpnError, HandshakeError, SslAcceptor, SslMethod, SslOptions, SslStream, SslVersion};
+use openssl::x509::X509;
 use serde::{Deserialize, Serialize};
+use socket2::{SockRef, TcpKeepalive};
 use threadpool::ThreadPool;
 
-use crate::record::TemperatureRecord;
+use crate::limits::{ConnectionLimiter, LimitAction, LimitParameters, Limiter};
+use crate::metrics::Metrics;
+use crate::quarantine::{Quarantine, RejectedRecord, RejectionStage};
+use crate::record::{ReceivedRecord, TemperatureRecord};
+use crate::shared::read_shared;
+use crate::systemd;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Number of threads handling connections and the connection limit if `max_connections` is not set.
+const DEFAULT_CONNECTION_THREADS: usize = 10;
+
+/// Interval the listener waits for new connections before checking whether it should finish.
+const ACCEPT_INTERVAL: time::Duration = time::Duration::from_millis(10);
+
+/// Maximum time a throttled connection waits before it checks whether it should finish.
+const THROTTLE_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+/// Interval the tls handshake waits for more data from the peer before it is continued.
+const HANDSHAKE_POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 /// Struct representing the parameters needed for establishing a simple UDP or TCP socket.
 pub struct SocketParameters
 {
@@ -24,20 +47,370 @@ pub struct SocketParameters
     pub address: String,
     /// The port the socket should listen on.
     pub port: u32,
+    /// Networks allowed to connect in CIDR notation, e.g. `10.0.20.0/24`. All networks are allowed
+    /// if empty.
+    #[serde(default)]
+    pub allow: Vec<IpNet>,
+    /// Networks denied to connect in CIDR notation, taking precedence over `allow`.
+    #[serde(default)]
+    pub deny: Vec<IpNet>,
+}
+
+impl SocketParameters {
+    /// Returns whether a peer is allowed to connect by the `allow` and `deny` lists.
+    ///
+    /// IPv4 addresses mapped to IPv6, as reported by sockets listening on `::`, are matched as
+    /// IPv4 addresses.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer_ip` - The address of the peer.
+    ///
+    pub fn is_allowed(&self, peer_ip: IpAddr) -> bool
+    {
+        let peer_ip = peer_ip.to_canonical();
+        if self.deny.iter().any(|network| network.contains(&peer_ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(&peer_ip))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 /// Struct representing the parameters for establishing a TCP/TLS socket.
 ///
-/// This socket is encrypted with a pkcs12 certificate/key file.
+/// This socket is encrypted either with a pkcs12 certificate/key file or with a PEM certificate
+/// chain and a PEM private key.
 pub struct TlsSocketParameters
 {
     /// The prarameters for establishing a socket.
+    #[serde(default = "default_socket_params")]
     pub socket_params: SocketParameters,
     /// The location of the pkcs12 cert/key file.
-    pub pkcs12_identity_file: String,
+    #[serde(default)]
+    pub pkcs12_identity_file: Option<String>,
     /// The password to unlock the encrypted key pair.
-    pub pkcs12_file_password: String,
+    #[serde(default)]
+    pub pkcs12_file_password: Option<String>,
+    /// The file to read the pkcs12 password from instead, see the `secrets` module.
+    #[serde(default)]
+    pub pkcs12_file_password_file: Option<String>,
+    /// The location of the PEM certificate chain, starting with the certificate of the socket.
+    #[serde(default)]
+    pub certificate_chain_file: Option<String>,
+    /// The location of the PEM private key.
+    #[serde(default)]
+    pub private_key_file: Option<String>,
+    /// The passphrase of the PEM private key, if it is encrypted.
+    #[serde(default)]
+    pub private_key_password: Option<String>,
+    /// The file to read the private key passphrase from instead, see the `secrets` module.
+    #[serde(default)]
+    pub private_key_password_file: Option<String>,
+    /// Adopt the listening socket passed by systemd socket activation instead of binding
+    /// `socket_params` if one is available.
+    #[serde(default = "default_systemd_socket_activation")]
+    pub systemd_socket_activation: bool,
+    /// Interval in seconds the identity files are checked for changes, `0` disables the check.
+    #[serde(default = "default_identity_watch_interval_secs")]
+    pub identity_watch_interval_secs: u64,
+    /// The minimum tls version accepted from clients.
+    #[serde(default = "default_min_tls_version")]
+    pub min_tls_version: TlsVersion,
+    /// The maximum tls version accepted from clients, the highest supported version if not set.
+    #[serde(default)]
+    pub max_tls_version: Option<TlsVersion>,
+    /// The OpenSSL cipher list used for TLS 1.2 and below, e.g. `ECDHE-RSA-AES128-GCM-SHA256`.
+    #[serde(default)]
+    pub cipher_list: Option<String>,
+    /// The colon separated TLS 1.3 cipher suites, e.g. `TLS_AES_256_GCM_SHA384`.
+    #[serde(default)]
+    pub cipher_suites: Option<String>,
+    /// The ALPN protocol identifier selected if offered by the client.
+    #[serde(default)]
+    pub alpn_protocol: Option<String>,
+    /// Time in seconds after which an incomplete tls handshake is aborted.
+    #[serde(default = "default_handshake_timeout_secs")]
+    pub handshake_timeout_secs: u64,
+    /// Time in seconds after which a connection without received data is closed, `0` disables the timeout.
+    #[serde(default = "default_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+    /// TCP keepalive settings for accepted connections or `None` to use the system defaults.
+    #[serde(default)]
+    pub tcp_keepalive: Option<TcpKeepaliveParameters>,
+    /// Limits of the connections and their records.
+    #[serde(default)]
+    pub limits: LimitParameters,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+/// Struct representing the TCP keepalive settings of accepted connections.
+///
+/// Keepalive probes detect peers that vanished without closing the connection, e.g. after a power
+/// loss, even while the idle timeout has not passed yet.
+pub struct TcpKeepaliveParameters
+{
+    /// Time in seconds the connection is idle before the first probe is sent.
+    pub time_secs: u64,
+    /// Time in seconds between two probes.
+    pub interval_secs: u64,
+    /// Number of unanswered probes after which the connection is closed.
+    pub count: u32,
+}
+
+impl Default for TcpKeepaliveParameters {
+    fn default() -> Self {
+        TcpKeepaliveParameters {
+            time_secs: 60,
+            interval_secs: 10,
+            count: 5,
+        }
+    }
+}
+
+impl TcpKeepaliveParameters {
+    /// Returns the matching socket keepalive settings.
+    fn tcp_keepalive(&self) -> TcpKeepalive
+    {
+        TcpKeepalive::new()
+            .with_time(time::Duration::from_secs(self.time_secs))
+            .with_interval(time::Duration::from_secs(self.interval_secs))
+            .with_retries(self.count)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, PartialOrd)]
+/// Enum representing the tls protocol versions, ordered from the oldest to the newest version.
+pub enum TlsVersion
+{
+    /// TLS 1.0
+    #[serde(rename = "1.0")]
+    Tlsv10,
+    /// TLS 1.1
+    #[serde(rename = "1.1")]
+    Tlsv11,
+    /// TLS 1.2
+    #[serde(rename = "1.2")]
+    Tlsv12,
+    /// TLS 1.3
+    #[serde(rename = "1.3")]
+    Tlsv13,
+}
+
+impl TlsVersion {
+    /// Returns the matching OpenSSL protocol version.
+    fn ssl_version(self) -> SslVersion
+    {
+        match self {
+            TlsVersion::Tlsv10 => SslVersion::TLS1,
+            TlsVersion::Tlsv11 => SslVersion::TLS1_1,
+            TlsVersion::Tlsv12 => SslVersion::TLS1_2,
+            TlsVersion::Tlsv13 => SslVersion::TLS1_3,
+        }
+    }
+
+    /// Returns the version as written in the configuration.
+    fn as_str(self) -> &'static str
+    {
+        match self {
+            TlsVersion::Tlsv10 => "1.0",
+            TlsVersion::Tlsv11 => "1.1",
+            TlsVersion::Tlsv12 => "1.2",
+            TlsVersion::Tlsv13 => "1.3",
+        }
+    }
+}
+
+/// Default minimum tls version accepted from clients.
+fn default_min_tls_version() -> TlsVersion
+{
+    TlsVersion::Tlsv12
+}
+
+/// Struct representing the private key and certificates of the socket.
+struct TlsIdentity
+{
+    /// The private key of the socket.
+    private_key: PKey<Private>,
+    /// The certificate of the socket.
+    certificate: X509,
+    /// The intermediate certificates sent along with the certificate.
+    chain: Vec<X509>,
+}
+
+impl TlsSocketParameters {
+    /// Returns the locations of the configured identity files.
+    pub fn identity_files(&self) -> Vec<&str>
+    {
+        [&self.pkcs12_identity_file, &self.certificate_chain_file, &self.private_key_file]
+            .iter()
+            .filter_map(|file| file.as_deref())
+            .collect()
+    }
+}
+
+impl Default for TlsSocketParameters {
+    fn default() -> Self {
+        TlsSocketParameters {
+            socket_params: default_socket_params(),
+            pkcs12_identity_file: None,
+            pkcs12_file_password: None,
+            pkcs12_file_password_file: None,
+            certificate_chain_file: None,
+            private_key_file: None,
+            private_key_password: None,
+            private_key_password_file: None,
+            systemd_socket_activation: default_systemd_socket_activation(),
+            identity_watch_interval_secs: default_identity_watch_interval_secs(),
+            min_tls_version: default_min_tls_version(),
+            max_tls_version: None,
+            cipher_list: None,
+            cipher_suites: None,
+            alpn_protocol: None,
+            handshake_timeout_secs: default_handshake_timeout_secs(),
+            idle_timeout_secs: default_idle_timeout_secs(),
+            tcp_keepalive: None,
+            limits: LimitParameters::default(),
+        }
+    }
+}
+
+impl TlsSocketParameters {
+    /// Checks the settings that cannot be checked by their type alone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `max_tls_version` is lower than the `min_tls_version`.
+    ///
+    pub fn validate(&self) -> Result<(), String>
+    {
+        match self.max_tls_version {
+            Some(max_tls_version) if max_tls_version < self.min_tls_version => {
+                Err(format!("max_tls_version {} is lower than min_tls_version {}",
+                            max_tls_version.as_str(), self.min_tls_version.as_str()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Default address and port the socket listens on.
+fn default_socket_params() -> SocketParameters
+{
+    SocketParameters {
+        address: String::from("0.0.0.0"),
+        port: 31454,
+        allow: vec![],
+        deny: vec![],
+    }
+}
+
+/// Default for adopting the socket passed by systemd socket activation, the socket is only used if
+/// one was passed.
+fn default_systemd_socket_activation() -> bool
+{
+    true
+}
+
+/// Default interval in seconds the identity files are checked for changes.
+fn default_identity_watch_interval_secs() -> u64
+{
+    60
+}
+
+/// Default time in seconds after which an incomplete tls handshake is aborted.
+fn default_handshake_timeout_secs() -> u64
+{
+    10
+}
+
+/// Default time in seconds after which an idle connection is closed.
+fn default_idle_timeout_secs() -> u64
+{
+    300
+}
+
+/// Function to read the time the tls identity was last modified.
+///
+/// Symbolic links are followed, so renewals that replace the link target are detected.
+///
+/// # Arguments
+///
+/// * `params` - The parameters of the tls socket.
+///
+/// # Returns
+///
+/// The latest modification time of the identity files, or `None` if none of them can be read.
+///
+pub fn identity_modified(params: &TlsSocketParameters) -> Option<time::SystemTime>
+{
+    params.identity_files()
+        .into_iter()
+        .filter_map(|file| fs::metadata(file).and_then(|metadata| metadata.modified()).ok())
+        .max()
+}
+
+/// Function to enforce a rate limit on a received record.
+///
+/// The first exceeded limit of a connection is logged as a warning, further ones as debug messages.
+///
+/// # Arguments
+///
+/// * `take_token` - Takes a token from the bucket, returning the time until the next token is
+///   available if it is empty.
+///
+/// * `scope` - The scope of the bucket, `connection` or `sensor`.
+///
+/// * `action` - The action taken if the bucket is empty.
+///
+/// * `logged` - Whether an exceeded limit was already logged for the connection.
+///
+/// * `metrics` - The metrics updated for every rate limited record.
+///
+/// * `thread_finish` - Stops throttling if the thread should finish running.
+///
+/// # Returns
+///
+/// `true` if the record is accepted, `false` if the connection should be closed.
+///
+fn wait_for_token<F>(mut take_token: F,
+                     scope: &'static str,
+                     action: LimitAction,
+                     logged: &mut bool,
+                     metrics: &Metrics,
+                     thread_finish: &AtomicBool) -> bool
+    where F: FnMut() -> Option<time::Duration>
+{
+    let mut wait = match take_token() {
+        Some(wait) => wait,
+        None => return true,
+    };
+
+    metrics.record_rate_limited(scope);
+    if *logged {
+        log::debug!(target: "dblogd::socket::tls", "Record exceeds the {} rate limit!", scope);
+    } else {
+        log::warn!(target: "dblogd::socket::tls", "Record exceeds the {} rate limit, {} the connection!", scope, match action {
+            LimitAction::Throttle => "throttling",
+            LimitAction::Disconnect => "closing",
+        });
+        *logged = true;
+    }
+
+    if action == LimitAction::Disconnect {
+        return false;
+    }
+    loop {
+        if thread_finish.load(Ordering::SeqCst) {
+            return false;
+        }
+        thread::sleep(wait.min(THROTTLE_INTERVAL));
+        wait = match take_token() {
+            Some(wait) => wait,
+            None => return true,
+        };
+    }
 }
 
 ///
@@ -49,12 +422,24 @@ pub struct TlsSocketParameters
 /// Received packages can not be longer than 512 bytes.
 /// Iff they are longer they will be capped.
 ///
+/// Payloads that are not valid UTF-8 or cannot be deserialized are passed to the quarantine.
+///
 /// # Arguments
 ///
 /// * `stream` - The TCP/Tls stream to communicate with the remote peer.
 ///
+/// * `peer_addr` - The address of the remote peer, if known.
+///
 /// * `tx` - Sender to transfer the valid data received from the remote host to the database thread.
 ///
+/// * `quarantine` - The quarantine for payloads that cannot be deserialized.
+///
+/// * `metrics` - The metrics updated for every received payload.
+///
+/// * `limiter` - The limits of the connection.
+///
+/// * `idle_timeout` - The time without received data after which the connection is closed, if any.
+///
 /// * `thread_finish` - Thread shared boolean to indicate if the thread should finish running.
 ///
 /// # Errors
@@ -67,19 +452,27 @@ pub struct TlsSocketParameters
 ///
 /// These errors will result in the method immediately exiting without raising a exception.
 ///
+#[allow(clippy::too_many_arguments)]
 fn handle_tls_stream(
-    mut stream: TlsStream<TcpStream>,
-    tx: Sender<TemperatureRecord>,
+    mut stream: SslStream<TcpStream>,
+    peer_addr: Option<SocketAddr>,
+    tx: Sender<ReceivedRecord>,
+    quarantine: Quarantine,
+    metrics: &Metrics,
+    mut limiter: ConnectionLimiter,
+    idle_timeout: Option<time::Duration>,
     thread_finish: Arc<AtomicBool>)
 {
 
     match stream.get_mut().set_read_timeout(Some(time::Duration::from_millis(100))) {
         Ok(_) => {}
         Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
             log::error!(target: "dblogd::socket::tls", "Unable to set connection nonblocking: \'{}\'", err);
             match stream.shutdown() {
                 Ok(_) => {}
                 Err(err) => {
+                    let _error = log_mdc::insert_scoped("error", err.to_string());
                     log::error!(target: "dblogd::socket::tls", "Unable to close tls connection: \'{}\'", err);
                 }
             };
@@ -87,6 +480,9 @@ fn handle_tls_stream(
         }
     };
 
+    let exceeded_action = limiter.exceeded_action();
+    let mut rate_limit_logged = false;
+    let mut last_received = time::Instant::now();
     while !thread_finish.load(Ordering::SeqCst) {
 
         let mut recv_vec: [u8; 512] = [0; 512];
@@ -97,20 +493,44 @@ fn handle_tls_stream(
             }
             Ok(bytes_read) => bytes_read,
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                // wait until network socket is ready, typically implemented
-                // via platform-specific APIs such as epoll or IOCP
+                if idle_timeout.is_some_and(|idle_timeout| last_received.elapsed() >= idle_timeout) {
+                    log::info!(target: "dblogd::socket::tls", "Closing connection idle for {} seconds!", last_received.elapsed().as_secs());
+                    metrics.idle_timeouts.fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
                 continue;
             }
-            Err(err) => {
-                log::error!(target: "dblogd::socket::tls", "Socket cannot read data: \'{}\'", err);
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
                 continue;
             }
+            Err(err) => {
+                // Includes the ETIMEDOUT of unanswered keepalive probes and connection resets.
+                let _error = log_mdc::insert_scoped("error", err.to_string());
+                log::warn!(target: "dblogd::socket::tls", "Socket cannot read data, closing connection: \'{}\'", err);
+                return;
+            }
         };
 
+        last_received = time::Instant::now();
+        let received_at = Utc::now();
+        if !wait_for_token(|| limiter.take_connection_token(), "connection", exceeded_action,
+                           &mut rate_limit_logged, metrics, &thread_finish) {
+            break;
+        }
+        metrics.bytes_received.fetch_add(recv_bytes_read as u64, Ordering::Relaxed);
+
         let recv_string = match std::str::from_utf8(&recv_vec[..recv_bytes_read]) {
             Ok(string) => String::from(string),
             Err(err) => {
+                let _error = log_mdc::insert_scoped("error", err.to_string());
                 log::warn!(target: "dblogd::socket::tls", "Socket received non UTF-8 data: \'{}\'", err);
+                metrics.utf8_decode_errors.fetch_add(1, Ordering::Relaxed);
+                quarantine.reject(RejectedRecord::new(
+                    openssl::base64::encode_block(&recv_vec[..recv_bytes_read]),
+                    peer_addr,
+                    received_at,
+                    RejectionStage::Decode,
+                    err.to_string()));
                 continue;
             }
         };
@@ -120,14 +540,41 @@ fn handle_tls_stream(
         let json_buf_record = match serde_json::from_str::<TemperatureRecord>(recv_data_str_trimmed) {
             Ok(result) => result,
             Err(err) => {
+                let _error = log_mdc::insert_scoped("error", err.to_string());
                 log::error!(target: "dblogd::socket::tls", "Recieved data cannot be deserialized via JSON: \'{}\'", err);
+                metrics.json_decode_errors.fetch_add(1, Ordering::Relaxed);
+                quarantine.reject(RejectedRecord::new(
+                    String::from(recv_data_str_trimmed),
+                    peer_addr,
+                    received_at,
+                    RejectionStage::Deserialize,
+                    err.to_string()));
                 continue;
             }
         };
 
-        match tx.send(json_buf_record) {
-            Ok(_) => log::debug!(target: "dblogd::socket::tls", "Send message to database thread!"),
+        let _sensor_name = log_mdc::insert_scoped("sensor_name", json_buf_record.sensor_name.clone());
+        if !wait_for_token(|| limiter.take_sensor_token(json_buf_record.sensor_name.as_str()), "sensor", exceeded_action,
+                           &mut rate_limit_logged, metrics, &thread_finish) {
+            break;
+        }
+
+        let received_record = ReceivedRecord {
+            record: json_buf_record,
+            payload: String::from(recv_data_str_trimmed),
+            peer_addr,
+            received_at,
+        };
+
+        metrics.queue_depth.fetch_add(1, Ordering::Relaxed);
+        match tx.send(received_record) {
+            Ok(_) => {
+                metrics.records_received.fetch_add(1, Ordering::Relaxed);
+                log::debug!(target: "dblogd::socket::tls", "Send message to database thread!");
+            }
             Err(err) => {
+                metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
+                let _error = log_mdc::insert_scoped("error", err.to_string());
                 log::error!(target: "dblogd::socket::tls", "Could not send message to database thread: \'{}\'", err);
             }
         };
@@ -135,132 +582,399 @@ fn handle_tls_stream(
     match stream.shutdown() {
         Ok(_) => {}
         Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
             log::error!(target: "dblogd::socket::tls", "Unable to close tls connection: \'{}\'", err);
         }
     };
 }
 
-/// Function to perform a tls handshake if the Tls stream has been interrupted in a nonblocking mode.
-///
-/// This allows to finish a intterupted tls handshake.
+/// Function to perform the tls handshake of an accepted connection.
 ///
-/// **Warning:** This function will retry this operation for a infinite time if the operation keeps
-/// getting interrupted. There is no guarantee this will not result in a stack overflow.
+/// The handshake is driven in nonblocking mode, so a peer that sends its handshake slowly or not at
+/// all cannot hold the connection thread past the deadline. Failures and timeouts are logged and
+/// counted in the metrics.
 ///
 /// # Arguments
 ///
-/// * `incomplete_handshake_stream` - The interrupted stream. The function will try to establish a
-///     handshake on this stream.
-/// * `stream` - Optional either containing the established stream or non if no stream could be established.
-///     This is a **output** parameter.
+/// * `tls_acceptor` - The acceptor used for the tls handshake.
+///
+/// * `stream` - The accepted tcp stream.
+///
+/// * `handshake_timeout` - Time after which an incomplete handshake is aborted.
+///
+/// * `metrics` - The metrics updated for failed and timed out handshakes.
 ///
-/// # Future
+/// # Returns
 ///
-/// This function will probably be reworked in the future as it is suboptimal is every way.
+/// The established tls stream in blocking mode, or `None` if the handshake failed or timed out.
 ///
-fn tls_handshake(incomplete_handshake_stream: MidHandshakeTlsStream<TcpStream>, stream: &mut Option<TlsStream<TcpStream>>)
+fn tls_handshake(tls_acceptor: &SslAcceptor,
+                 stream: TcpStream,
+                 handshake_timeout: time::Duration,
+                 metrics: &Metrics) -> Option<SslStream<TcpStream>>
 {
-    match incomplete_handshake_stream.handshake() {
-        Ok(tls_stream) => {
-            *stream = Some(tls_stream);
-            return;
+    match stream.set_nonblocking(true) {
+        Ok(_) => {}
+        Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
+            log::error!(target: "dblogd::socket", "Unable to set connection nonblocking for the tls handshake: \'{}\'", err);
+            metrics.tls_handshake_failures.fetch_add(1, Ordering::Relaxed);
+            return None;
         }
-        Err(err) => match err {
-            HandshakeError::WouldBlock(handshake_conn) => {
-                tls_handshake(handshake_conn, stream);
-                return;
+    };
+
+    let deadline = time::Instant::now() + handshake_timeout;
+    let mut handshake = tls_acceptor.accept(stream);
+    loop {
+        match handshake {
+            Ok(tls_stream) => {
+                return match tls_stream.get_ref().set_nonblocking(false) {
+                    Ok(_) => Some(tls_stream),
+                    Err(err) => {
+                        let _error = log_mdc::insert_scoped("error", err.to_string());
+                        log::error!(target: "dblogd::socket", "Unable to set connection blocking after the tls handshake: \'{}\'", err);
+                        metrics.tls_handshake_failures.fetch_add(1, Ordering::Relaxed);
+                        None
+                    }
+                };
             }
-            HandshakeError::Failure(err) => {
-                log::error!(target: "dblogd::socket", "Could not perform tls handshake: \'{}\'", err);
-                return;
+            Err(HandshakeError::WouldBlock(handshake_conn)) => {
+                if time::Instant::now() >= deadline {
+                    log::warn!(target: "dblogd::socket", "Tls handshake not completed within {} seconds, closing connection!", handshake_timeout.as_secs());
+                    metrics.tls_handshake_timeouts.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                thread::sleep(HANDSHAKE_POLL_INTERVAL);
+                handshake = handshake_conn.handshake();
+            }
+            Err(HandshakeError::Failure(handshake_conn)) => {
+                let _error = log_mdc::insert_scoped("error", handshake_conn.error().to_string());
+                log::error!(target: "dblogd::socket", "Could not perform tls handshake: \'{}\'", handshake_conn.error());
+                metrics.tls_handshake_failures.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            Err(HandshakeError::SetupFailure(err)) => {
+                let _error = log_mdc::insert_scoped("error", err.to_string());
+                log::error!(target: "dblogd::socket", "Could not set up tls handshake: \'{}\'", err);
+                metrics.tls_handshake_failures.fetch_add(1, Ordering::Relaxed);
+                return None;
             }
         }
+    }
+}
+
+/// Function to read a file completely.
+///
+/// # Arguments
+///
+/// * `path` - The location of the file.
+///
+/// * `description` - The description of the file used in error messages.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - The content of the file on success.
+///
+/// * `Err(...)` - If the file cannot be opened or read.
+///
+fn read_file(path: &str, description: &str) -> Result<Vec<u8>, String>
+{
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(format!("Could not open {} \'{}\': \'{}\'", description, path, err));
+        }
     };
+    let mut content = vec![];
+    match file.read_to_end(&mut content) {
+        Ok(_) => Ok(content),
+        Err(err) => Err(format!("Could not read {} \'{}\': \'{}\'", description, path, err)),
+    }
 }
 
-/// Thread function for the socket functions.
+/// Function to load the identity of the socket.
 ///
-/// This function accepts incoming connections and allows them to send encrypted json data that will
-/// be relayed to the database thread.
+/// Either a pkcs12 identity file or a PEM certificate chain and private key have to be configured.
 ///
-/// This function will run until the `thread_finish` parameter was set or the socket is closed by a error.
+/// # Arguments
+///
+/// * `params` - Parameters for the socket and the tls connection.
+///
+/// # Returns
+///
+/// * `Ok(TlsIdentity)` - The identity on success.
+///
+/// * `Err(...)` - If no or both identities are configured or it cannot be read or decrypted.
+///
+fn load_identity(params: &TlsSocketParameters) -> Result<TlsIdentity, String>
+{
+    match (&params.pkcs12_identity_file, &params.certificate_chain_file, &params.private_key_file) {
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+            Err("Both pkcs12_identity_file and certificate_chain_file/private_key_file are configured, only one identity may be set!".to_string())
+        }
+        (Some(pkcs12_identity_file), None, None) => {
+            let pkcs12_identity = read_file(pkcs12_identity_file, "pkcs12 identity")?;
+            let password = params.pkcs12_file_password.as_deref().unwrap_or("");
+            let parsed = match Pkcs12::from_der(&pkcs12_identity).and_then(|pkcs12| pkcs12.parse2(password)) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    return Err(format!("Could create identity from pkcs12: \'{}\'", err));
+                }
+            };
+            match (parsed.pkey, parsed.cert) {
+                (Some(private_key), Some(certificate)) => Ok(TlsIdentity {
+                    private_key,
+                    certificate,
+                    chain: parsed.ca.map(|ca| ca.into_iter().collect()).unwrap_or_default(),
+                }),
+                _ => Err("Could create identity from pkcs12: \'The file contains no private key or certificate\'".to_string()),
+            }
+        }
+        (None, Some(certificate_chain_file), Some(private_key_file)) => {
+            let certificate_chain = read_file(certificate_chain_file, "certificate chain")?;
+            let private_key_pem = read_file(private_key_file, "private key")?;
+
+            let private_key = match &params.private_key_password {
+                Some(password) => PKey::private_key_from_pem_passphrase(&private_key_pem, password.as_bytes()),
+                None => PKey::private_key_from_pem(&private_key_pem),
+            };
+            let private_key = match private_key {
+                Ok(key) => key,
+                Err(err) => {
+                    return Err(format!("Could not decode private key: \'{}\'", err));
+                }
+            };
+
+            let mut certificates = match X509::stack_from_pem(&certificate_chain) {
+                Ok(certificates) => certificates.into_iter(),
+                Err(err) => {
+                    return Err(format!("Could not decode certificate chain: \'{}\'", err));
+                }
+            };
+            match certificates.next() {
+                Some(certificate) => Ok(TlsIdentity {
+                    private_key,
+                    certificate,
+                    chain: certificates.collect(),
+                }),
+                None => Err(format!("Could not decode certificate chain: \'{}\' contains no certificate", certificate_chain_file)),
+            }
+        }
+        _ => Err("Neither a pkcs12 identity file nor a certificate chain and private key file are configured!".to_string()),
+    }
+}
+
+/// Function to select the configured ALPN protocol from the protocols offered by the client.
 ///
 /// # Arguments
 ///
-/// * `tx` - Sender that is used to pass valid data to the database thread.
+/// * `protocol` - The configured protocol identifier.
 ///
-/// * `thread_finish` - Indicates that the thread should finish operation and should return.
+/// * `client_protocols` - The protocols offered by the client in the ALPN wire format.
 ///
-/// * `params` - Parameters for the socket and the tls connection.
+/// # Returns
 ///
-/// # Errors
+/// The matching protocol of the client, or `None` if the client did not offer it.
 ///
-/// Errors occur when one of the following conditions is met:
+fn select_alpn_protocol<'a>(protocol: &[u8], client_protocols: &'a [u8]) -> Option<&'a [u8]>
+{
+    let mut remaining = client_protocols;
+    while let Some((&length, rest)) = remaining.split_first() {
+        let length = length as usize;
+        if rest.len() < length {
+            return None;
+        }
+        let (offered, rest) = rest.split_at(length);
+        if offered == protocol {
+            return Some(offered);
+        }
+        remaining = rest;
+    }
+    None
+}
+
+/// Function to create the tls acceptor for the socket from the configured identity.
 ///
-/// * The identity for the TLS connection cannot be found.
+/// The acceptor starts from version 4 of the intermediate configuration recommended by Mozilla,
+/// whose cipher list still includes the RSA key exchange and CBC ciphers of older devices. The
+/// protocol options of the preset are cleared, so only the configured protocol versions apply. The
+/// configured ciphers and ALPN protocol are applied on top.
 ///
-/// * The socket cannot be created or listened to.
+/// If TLS 1.0 or 1.1 is accepted with OpenSSL 3, the security level is lowered to 0, as these
+/// versions are refused at any higher level. The level applies to every connection, so it also
+/// permits small keys and weak ciphers or groups for TLS 1.2 and 1.3, unless they are excluded by
+/// the identity and the cipher list. Only accept these versions if devices require them.
 ///
-/// * The socket cannot be set to nonblocking mode.
+/// # Arguments
 ///
-/// These errors will result in the method immediately exiting without raising a exception.
+/// * `params` - Parameters for the socket and the tls connection.
+///
+/// # Returns
+///
+/// * `Ok(SslAcceptor)` - The acceptor on success.
+///
+/// * `Err(...)` - If the identity cannot be read, a tls setting is invalid or the acceptor cannot
+///   be created.
 ///
-pub fn thread_tcp_listener_socket(tx: Sender<TemperatureRecord>, thread_finish: Arc<AtomicBool>, params: TlsSocketParameters)
+pub fn build_tls_acceptor(params: &TlsSocketParameters) -> Result<SslAcceptor, String>
 {
-    let mut pkcs12_identity_file = match File::open(params.pkcs12_identity_file) {
-        Ok(file) => file,
+    let identity = load_identity(params)?;
+
+    let mut tls_acceptor_builder = match SslAcceptor::mozilla_intermediate(SslMethod::tls_server()) {
+        Ok(builder) => builder,
         Err(err) => {
-            log::error!(target: "dblogd::socket", "Could not open pkcs12 identity: \'{}\'", err);
-            thread_finish.store(true, Ordering::SeqCst);
-            return;
+            return Err(format!("Could not create tls acceptor builder: \'{}\'", err));
         }
     };
-    let mut pkcs12_identity = vec![];
-    match pkcs12_identity_file.read_to_end(&mut pkcs12_identity) {
+    tls_acceptor_builder.clear_options(SslOptions::NO_TLSV1 | SslOptions::NO_TLSV1_1 | SslOptions::NO_TLSV1_2 | SslOptions::NO_TLSV1_3);
+    if params.min_tls_version < TlsVersion::Tlsv12 && openssl::version::number() >= 0x3000_0000 {
+        // OpenSSL 3 refuses the SHA-1 based handshake of TLS 1.0 and 1.1 above security level 0.
+        log::warn!(target: "dblogd::socket", "Accepting tls {} lowers the OpenSSL security level to 0 for all connections!",
+                   params.min_tls_version.as_str());
+        tls_acceptor_builder.set_security_level(0);
+    }
+
+    match tls_acceptor_builder.set_private_key(&identity.private_key)
+        .and_then(|_| tls_acceptor_builder.set_certificate(&identity.certificate))
+        .and_then(|_| identity.chain.into_iter().try_for_each(|cert| tls_acceptor_builder.add_extra_chain_cert(cert)))
+        .and_then(|_| tls_acceptor_builder.check_private_key()) {
         Ok(_) => {}
         Err(err) => {
-            log::error!(target: "dblogd::socket", "Could not read pkcs12 identity: \'{}\'", err);
-            thread_finish.store(true, Ordering::SeqCst);
-            return;
+            return Err(format!("Could create tls acceptor from identity: \'{}\'", err));
         }
     };
 
-    let identity = match Identity::from_pkcs12(&pkcs12_identity, params.pkcs12_file_password.as_str()) {
-        Ok(idn) => idn,
+    match tls_acceptor_builder.set_min_proto_version(Some(params.min_tls_version.ssl_version()))
+        .and_then(|_| tls_acceptor_builder.set_max_proto_version(params.max_tls_version.map(TlsVersion::ssl_version))) {
+        Ok(_) => {}
         Err(err) => {
-            log::error!(target: "dblogd::socket", "Could create identity from pkcs12: \'{}\'", err);
-            thread_finish.store(true, Ordering::SeqCst);
-            return;
+            return Err(format!("Could not set tls protocol versions: \'{}\'", err));
         }
     };
 
-    let mut tls_acceptor_builder = TlsAcceptor::builder(identity);
-    tls_acceptor_builder.min_protocol_version(Some(Protocol::Tlsv12));
+    if let Some(cipher_list) = &params.cipher_list {
+        match tls_acceptor_builder.set_cipher_list(cipher_list) {
+            Ok(_) => {}
+            Err(err) => {
+                return Err(format!("Could not set cipher list \'{}\': \'{}\'", cipher_list, err));
+            }
+        };
+    }
 
+    if let Some(cipher_suites) = &params.cipher_suites {
+        match tls_acceptor_builder.set_ciphersuites(cipher_suites) {
+            Ok(_) => {}
+            Err(err) => {
+                return Err(format!("Could not set cipher suites \'{}\': \'{}\'", cipher_suites, err));
+            }
+        };
+    }
 
-    let tls_acceptor = match tls_acceptor_builder.build() {
-        Ok(acc) => acc,
-        Err(err) => {
-            log::error!(target: "dblogd::socket", "Could create tls acceptor from identity: \'{}\'", err);
-            thread_finish.store(true, Ordering::SeqCst);
-            return;
+    if let Some(alpn_protocol) = &params.alpn_protocol {
+        if alpn_protocol.is_empty() || alpn_protocol.len() > 255 {
+            return Err(format!("Invalid alpn protocol \'{}\': must be between 1 and 255 bytes long", alpn_protocol));
         }
+        let alpn_protocol = alpn_protocol.clone().into_bytes();
+        tls_acceptor_builder.set_alpn_select_callback(move |_, client_protocols| {
+            select_alpn_protocol(&alpn_protocol, client_protocols).ok_or(AlpnError::NOACK)
+        });
+    }
+
+    Ok(tls_acceptor_builder.build())
+}
+
+/// Thread function for the socket functions.
+///
+/// This function accepts incoming connections and allows them to send encrypted json data that will
+/// be relayed to the database thread.
+///
+/// The tls acceptor is shared with the main thread, which may replace it at any time. New
+/// connections use the current acceptor, established connections are not affected.
+///
+/// Connections from addresses denied by the `allow` and `deny` lists of the socket parameters or
+/// exceeding the connection limits are closed before the tls handshake.
+///
+/// This function will run until the `thread_finish` parameter was set or the socket is closed by a error.
+/// Once `thread_finish` is set, no further connections are accepted and the open connections are
+/// closed after their current message.
+///
+/// # Arguments
+///
+/// * `tx` - Sender that is used to pass valid data to the database thread.
+///
+/// * `quarantine` - The quarantine for payloads that cannot be deserialized.
+///
+/// * `metrics` - The metrics updated for every connection and payload.
+///
+/// * `tls_acceptor` - The acceptor used for the tls handshake of new connections.
+///
+/// * `socket_params` - The `allow` and `deny` lists checked for new connections. The address and
+///   port are only used when the socket is opened.
+///
+/// * `thread_finish` - Indicates that the thread should finish operation and should return.
+///
+/// * `params` - Parameters for the socket and the tls connection.
+///
+/// # Errors
+///
+/// Errors occur when one of the following conditions is met:
+///
+/// * The socket cannot be created or listened to.
+///   If `systemd_socket_activation` is set and systemd passed a listening socket, it is used instead.
+///
+/// * Systemd passed more than one socket or a socket that is not a listening TCP socket.
+///
+/// * The socket cannot be set to nonblocking mode.
+///
+/// These errors will result in the method immediately exiting without raising a exception.
+///
+pub fn thread_tcp_listener_socket(tx: Sender<ReceivedRecord>,
+                                  quarantine: Quarantine,
+                                  metrics: Arc<Metrics>,
+                                  tls_acceptor: Arc<RwLock<SslAcceptor>>,
+                                  socket_params: Arc<RwLock<SocketParameters>>,
+                                  thread_finish: Arc<AtomicBool>, params: TlsSocketParameters)
+{
+    let activated_listener = if params.systemd_socket_activation {
+        match systemd::listen_socket() {
+            Ok(activated_listener) => activated_listener,
+            Err(err) => {
+                let _error = log_mdc::insert_scoped("error", err.clone());
+                log::error!(target: "dblogd::socket", "Could not use socket from systemd socket activation: \'{}\'", err);
+                thread_finish.store(true, Ordering::SeqCst);
+                return;
+            }
+        }
+    } else {
+        if !systemd::listen_fds().is_empty() {
+            log::warn!(target: "dblogd::socket", "Ignoring socket passed by systemd socket activation, systemd_socket_activation is disabled!");
+        }
+        None
     };
 
-    let tcp_listener = match TcpListener::bind(format!("{}:{}", params.socket_params.address, params.socket_params.port)) {
-        Ok(listener) => listener,
-        Err(err) => {
-            log::error!(target: "dblogd::socket", "Could not open tcp listener: \'{}\'", err);
-            thread_finish.store(true, Ordering::SeqCst);
-            return;
+    let tcp_listener = match activated_listener {
+        Some(listener) => {
+            log::info!(target: "dblogd::socket", "Using socket passed by systemd socket activation!");
+            listener
+        }
+        None => match TcpListener::bind(format!("{}:{}", params.socket_params.address, params.socket_params.port)) {
+            Ok(listener) => listener,
+            Err(err) => {
+                let _error = log_mdc::insert_scoped("error", err.to_string());
+                log::error!(target: "dblogd::socket", "Could not open tcp listener: \'{}\'", err);
+                thread_finish.store(true, Ordering::SeqCst);
+                return;
+            }
         }
     };
     tcp_listener.set_nonblocking(true).expect("Cannot set non-blocking");
     match tcp_listener.local_addr() {
         Ok(res) => {
             log::info!(target: "dblogd::socket", "Socket Addr: \'{}\'", res);
+            metrics.listener_bound.store(true, Ordering::SeqCst);
         }
         Err(err) => {
+            let _error = log_mdc::insert_scoped("error", err.to_string());
             log::error!(target: "dblogd::socket", "Could not get socket address: \'{}\'", err);
             thread_finish.store(true, Ordering::SeqCst);
             return;
@@ -268,64 +982,181 @@ pub fn thread_tcp_listener_socket(tx: Sender<TemperatureRecord>, thread_finish:
     }
 
 
-    let thread_pool = ThreadPool::with_name(String::from("tls_threads"), 10);
-
-    for stream in tcp_listener.incoming() {
-        if thread_finish.load(Ordering::SeqCst) {
-            thread_pool.join();
-            return;
-        }
+    // Every open connection occupies a thread. Connections are only accepted while a thread is free,
+    // so they never queue in the pool, also if `max_connections` is not configured.
+    let mut limits = params.limits.clone();
+    if limits.max_connections == 0 {
+        limits.max_connections = DEFAULT_CONNECTION_THREADS;
+    }
+    let connection_threads = limits.max_connections;
+    let thread_pool = ThreadPool::with_name(String::from("tls_threads"), connection_threads);
+    let idle_timeout = match params.idle_timeout_secs {
+        0 => None,
+        idle_timeout_secs => Some(time::Duration::from_secs(idle_timeout_secs)),
+    };
+    let handshake_timeout = time::Duration::from_secs(params.handshake_timeout_secs);
+    let tcp_keepalive = params.tcp_keepalive.as_ref().map(TcpKeepaliveParameters::tcp_keepalive);
+    let limiter = Arc::new(Limiter::new(limits));
 
-        match stream {
-            Ok(stream) => {
-                let tls_acceptor = tls_acceptor.clone();
+    while !thread_finish.load(Ordering::SeqCst) {
+        match tcp_listener.accept() {
+            Ok((stream, addr)) => {
+                if !read_shared(&socket_params).is_allowed(addr.ip()) {
+                    let _peer_addr = log_mdc::insert_scoped("peer_addr", addr.to_string());
+                    // Denied connections are counted in the metrics, a scan of a denied network must not flood the log.
+                    log::debug!(target: "dblogd::socket", "Closing connection from denied address \'{}\'!", addr);
+                    metrics.connection_rejected("denied");
+                    continue;
+                }
+                let connection_limiter = match Limiter::open_connection(&limiter, Some(addr.ip())) {
+                    Ok(connection_limiter) => connection_limiter,
+                    Err(limit) => {
+                        let _peer_addr = log_mdc::insert_scoped("peer_addr", addr.to_string());
+                        log::warn!(target: "dblogd::socket", "Closing connection from \'{}\' exceeding {}!", addr, limit);
+                        metrics.connection_rejected(limit);
+                        continue;
+                    }
+                };
+                let tls_acceptor = read_shared(&tls_acceptor);
                 let finish_connection_thread = Arc::clone(&thread_finish);
                 let tx_connection = tx.clone();
+                let quarantine_connection = quarantine.clone();
+                let metrics_connection = Arc::clone(&metrics);
+                let tcp_keepalive_connection = tcp_keepalive.clone();
+                metrics.connections_accepted.fetch_add(1, Ordering::Relaxed);
 
                 thread_pool.execute(move || {
-                    let tls_stream = match tls_acceptor.accept::<TcpStream>(stream) {
-                        Ok(stream) => stream,
-                        Err(err) => {
-                            match err {
-                                HandshakeError::WouldBlock(handshake_conn) => {
-                                    let mut stream = Option::<TlsStream<TcpStream>>::None;
-                                    tls_handshake(handshake_conn, &mut stream);
-                                    match stream {
-                                        Some(tls_stream) => tls_stream,
-                                        None => {
-                                            log::error!(target: "dblogd::socket", "Could not perform tls handshake!");
-                                            return;
-                                        }
-                                    }
-                                }
-                                HandshakeError::Failure(err) => {
-                                    log::error!(target: "dblogd::socket", "Could not perform tls handshake: \'{}\'", err);
-                                    return;
-                                }
-                            }
-                        }
-                    };
-                    match tls_stream.get_ref().peer_addr() {
+                    let peer_addr = match stream.peer_addr() {
                         Ok(addr) => {
                             log::debug!(target: "dblogd::socket", "Connected to {}:{}", addr.ip(), addr.port());
+                            Some(addr)
                         }
                         Err(err) => {
+                            let _error = log_mdc::insert_scoped("error", err.to_string());
                             log::warn!(target: "dblogd::socket", "Could not get connection address: \'{}\'", err);
+                            None
                         }
                     };
-
-                    handle_tls_stream(tls_stream, tx_connection, finish_connection_thread);
+                    let _peer_addr = peer_addr.map(|addr| log_mdc::insert_scoped("peer_addr", addr.to_string()));
+                    if let Some(tcp_keepalive) = tcp_keepalive_connection {
+                        match SockRef::from(&stream).set_tcp_keepalive(&tcp_keepalive) {
+                            Ok(_) => {}
+                            Err(err) => {
+                                let _error = log_mdc::insert_scoped("error", err.to_string());
+                                log::warn!(target: "dblogd::socket", "Could not enable tcp keepalive: \'{}\'", err);
+                            }
+                        };
+                    }
+                    let tls_stream = match tls_handshake(&tls_acceptor, stream, handshake_timeout, &metrics_connection) {
+                        Some(tls_stream) => tls_stream,
+                        None => return,
+                    };
+                    metrics_connection.connections_active.fetch_add(1, Ordering::Relaxed);
+                    handle_tls_stream(tls_stream, peer_addr, tx_connection, quarantine_connection, &metrics_connection, connection_limiter, idle_timeout, finish_connection_thread);
+                    metrics_connection.connections_active.fetch_sub(1, Ordering::Relaxed);
                 });
             }
             Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
-                // wait until network socket is ready, typically implemented
-                // via platform-specific APIs such as epoll or IOCP
+                thread::sleep(ACCEPT_INTERVAL);
                 continue;
             }
             Err(err) => {
+                let _error = log_mdc::insert_scoped("error", err.to_string());
                 log::error!(target: "dblogd::socket", "Could not connect to tcp stream: \'{}\'", err);
                 continue;
             }
         }
     }
+
+    // Stop accepting connections before waiting for the open ones to finish their current message.
+    drop(tcp_listener);
+    metrics.listener_bound.store(false, Ordering::SeqCst);
+    log::info!(target: "dblogd::socket", "Stopped accepting connections, closing {} open connections!",
+               metrics.connections_active.load(Ordering::Relaxed));
+    thread_pool.join();
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn socket_params(allow: &[&str], deny: &[&str]) -> SocketParameters
+    {
+        SocketParameters {
+            address: String::from("::"),
+            port: 31454,
+            allow: allow.iter().map(|network| network.parse().unwrap()).collect(),
+            deny: deny.iter().map(|network| network.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn is_allowed_allows_all_without_lists()
+    {
+        let params = socket_params(&[], &[]);
+        assert!(params.is_allowed("192.168.20.7".parse().unwrap()));
+        assert!(params.is_allowed("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn is_allowed_checks_allow_list()
+    {
+        let params = socket_params(&["192.168.20.0/24", "2001:db8::/32"], &[]);
+        assert!(params.is_allowed("192.168.20.7".parse().unwrap()));
+        assert!(params.is_allowed("2001:db8::1".parse().unwrap()));
+        assert!(!params.is_allowed("192.168.21.7".parse().unwrap()));
+        assert!(!params.is_allowed("2001:db9::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn is_allowed_prefers_deny_list()
+    {
+        let params = socket_params(&["192.168.20.0/24"], &["192.168.20.1/32"]);
+        assert!(!params.is_allowed("192.168.20.1".parse().unwrap()));
+        assert!(params.is_allowed("192.168.20.2".parse().unwrap()));
+        assert!(!socket_params(&[], &["10.0.0.0/8"]).is_allowed("10.1.2.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn is_allowed_matches_ipv4_mapped_addresses()
+    {
+        let params = socket_params(&["192.168.20.0/24"], &["192.168.20.1/32"]);
+        assert!(params.is_allowed("::ffff:192.168.20.7".parse().unwrap()));
+        assert!(!params.is_allowed("::ffff:192.168.20.1".parse().unwrap()));
+        assert!(!params.is_allowed("::ffff:10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn validate_rejects_reversed_tls_versions()
+    {
+        let mut params = TlsSocketParameters::default();
+        assert!(params.validate().is_ok());
+        params.max_tls_version = Some(TlsVersion::Tlsv12);
+        assert!(params.validate().is_ok());
+        params.max_tls_version = Some(TlsVersion::Tlsv11);
+        assert!(params.validate().is_err());
+    }
+
+    #[test]
+    fn select_alpn_protocol_finds_configured_protocol()
+    {
+        let client_protocols = b"\x02h2\x08http/1.1\x08dblogd/1";
+        assert_eq!(select_alpn_protocol(b"dblogd/1", client_protocols), Some(&b"dblogd/1"[..]));
+        assert_eq!(select_alpn_protocol(b"h2", client_protocols), Some(&b"h2"[..]));
+    }
+
+    #[test]
+    fn select_alpn_protocol_rejects_missing_protocol()
+    {
+        assert_eq!(select_alpn_protocol(b"dblogd/1", b"\x02h2\x08http/1.1"), None);
+        assert_eq!(select_alpn_protocol(b"dblogd/1", b""), None);
+        assert_eq!(select_alpn_protocol(b"dblogd", b"\x08dblogd/1"), None);
+    }
+
+    #[test]
+    fn select_alpn_protocol_rejects_truncated_list()
+    {
+        assert_eq!(select_alpn_protocol(b"dblogd/1", b"\x02h2\x0adblogd/1"), None);
+    }
 }
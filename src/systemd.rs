@@ -0,0 +1,134 @@
+//! Module implementing the parts of the systemd service protocol used by the application.
+//!
+//! Notifications are sent to the socket in `$NOTIFY_SOCKET` as described in `sd_notify(3)`.
+//! Sockets passed by socket activation are read from `$LISTEN_FDS` as described in `sd_listen_fds(3)`.
+//! If the application is not started by systemd, all functions are no-ops.
+//!
+use std::{env, io, process, time};
+use std::net::TcpListener;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::io::{BorrowedFd, FromRawFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+use socket2::{Domain, SockRef, Type};
+
+/// The first file descriptor passed by socket activation, see `sd_listen_fds(3)`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Function to send a notification to the systemd service manager.
+///
+/// # Arguments
+///
+/// * `state` - The newline separated assignments to send, e.g. `READY=1`.
+///
+/// # Returns
+///
+/// * `Ok(true)` - If the notification was sent.
+///
+/// * `Ok(false)` - If the application was not started with a notification socket.
+///
+/// * `Err(...)` - If the notification could not be sent.
+///
+pub fn notify(state: &str) -> io::Result<bool>
+{
+    let notify_socket = match env::var_os("NOTIFY_SOCKET") {
+        Some(notify_socket) => notify_socket,
+        None => return Ok(false),
+    };
+    let notify_socket = notify_socket.to_string_lossy();
+
+    let socket = UnixDatagram::unbound()?;
+    match notify_socket.strip_prefix('@') {
+        Some(abstract_name) => {
+            let addr = SocketAddr::from_abstract_name(abstract_name.as_bytes())?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        None => {
+            socket.send_to(state.as_bytes(), notify_socket.as_ref())?;
+        }
+    };
+    Ok(true)
+}
+
+/// Function to read the interval the service manager expects watchdog keepalives in.
+///
+/// # Returns
+///
+/// Half of the watchdog timeout configured with `WatchdogSec=`, or `None` if the watchdog is
+/// disabled or meant for another process.
+///
+pub fn watchdog_interval() -> Option<time::Duration>
+{
+    if let Ok(watchdog_pid) = env::var("WATCHDOG_PID") {
+        if watchdog_pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+
+    let watchdog_usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    if watchdog_usec == 0 {
+        return None;
+    }
+    Some(time::Duration::from_micros(watchdog_usec / 2))
+}
+
+/// Function to read the file descriptors passed by socket activation.
+///
+/// # Returns
+///
+/// The passed file descriptors, or an empty vector if no sockets were passed to this process.
+///
+pub fn listen_fds() -> Vec<RawFd>
+{
+    let listen_pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    if listen_pid != Some(process::id()) {
+        return vec![];
+    }
+
+    match env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse::<RawFd>().ok()) {
+        Some(listen_fds) if listen_fds > 0 => (LISTEN_FDS_START..LISTEN_FDS_START + listen_fds).collect(),
+        _ => vec![],
+    }
+}
+
+/// Function to adopt the tcp listener passed by socket activation.
+///
+/// Exactly one socket has to be passed and it has to be a listening TCP socket, as configured by
+/// `ListenStream=` in `dblogd.socket`.
+///
+/// # Returns
+///
+/// * `Ok(Some(...))` - The passed listener.
+///
+/// * `Ok(None)` - If no sockets were passed to this process.
+///
+/// * `Err(...)` - If more than one socket or a socket other than a listening TCP socket was passed.
+///
+pub fn listen_socket() -> Result<Option<TcpListener>, String>
+{
+    let listen_fds = listen_fds();
+    let fd = match listen_fds.as_slice() {
+        [] => return Ok(None),
+        [fd] => *fd,
+        fds => return Err(format!("Expected a single socket from socket activation, got {}!", fds.len())),
+    };
+
+    // The file descriptor is only borrowed until it is verified to be a listening TCP socket.
+    let borrowed_fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    let socket = SockRef::from(&borrowed_fd);
+    let is_tcp = match (socket.domain(), socket.r#type()) {
+        (Ok(domain), Ok(socket_type)) => (domain == Domain::IPV4 || domain == Domain::IPV6) && socket_type == Type::STREAM,
+        (Err(err), _) | (_, Err(err)) => return Err(format!("Could not inspect socket {} from socket activation: {}", fd, err)),
+    };
+    if !is_tcp {
+        return Err(format!("Socket {} from socket activation is not a TCP socket!", fd));
+    }
+    match socket.is_listener() {
+        Ok(true) => {}
+        Ok(false) => return Err(format!("Socket {} from socket activation is not listening!", fd)),
+        Err(err) => return Err(format!("Could not inspect socket {} from socket activation: {}", fd, err)),
+    };
+
+    // The file descriptor was passed to this process by systemd, is verified above and only adopted once.
+    Ok(Some(unsafe { TcpListener::from_raw_fd(fd) }))
+}
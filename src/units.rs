@@ -0,0 +1,161 @@
+//! Module that contains the measurement units records can declare.
+//!
+//! Measurements are normalized to the canonical units of the database before insertion:
+//!
+//! * Temperature in degree celsius.
+//!
+//! * Relative humidity in percent.
+//!
+//! * Pressure in pascal.
+//!
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+/// Enum representing the units a temperature can be reported in.
+pub enum TemperatureUnit
+{
+    /// Degree celsius, the canonical unit.
+    #[default]
+    Celsius,
+    /// Degree fahrenheit.
+    Fahrenheit,
+    /// Kelvin.
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    /// Converts a temperature in this unit to degree celsius.
+    pub fn to_canonical(self, value: f64) -> f64
+    {
+        match self {
+            TemperatureUnit::Celsius => value,
+            TemperatureUnit::Fahrenheit => (value - 32.0) * 5.0 / 9.0,
+            TemperatureUnit::Kelvin => value - 273.15,
+        }
+    }
+
+    /// Returns the name of the unit as stored in the database.
+    pub fn as_str(self) -> &'static str
+    {
+        match self {
+            TemperatureUnit::Celsius => "celsius",
+            TemperatureUnit::Fahrenheit => "fahrenheit",
+            TemperatureUnit::Kelvin => "kelvin",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+/// Enum representing the units a relative humidity can be reported in.
+pub enum HumidityUnit
+{
+    /// Percent in the range `0..=100`, the canonical unit.
+    #[default]
+    Percent,
+    /// Fraction in the range `0..=1`.
+    Fraction,
+}
+
+impl HumidityUnit {
+    /// Converts a relative humidity in this unit to percent.
+    pub fn to_canonical(self, value: f64) -> f64
+    {
+        match self {
+            HumidityUnit::Percent => value,
+            HumidityUnit::Fraction => value * 100.0,
+        }
+    }
+
+    /// Returns the name of the unit as stored in the database.
+    pub fn as_str(self) -> &'static str
+    {
+        match self {
+            HumidityUnit::Percent => "percent",
+            HumidityUnit::Fraction => "fraction",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+/// Enum representing the units a pressure can be reported in.
+pub enum PressureUnit
+{
+    /// Hectopascal.
+    #[default]
+    #[serde(rename = "hpa")]
+    Hectopascal,
+    /// Pascal, the canonical unit.
+    #[serde(rename = "pa")]
+    Pascal,
+    /// Inch of mercury.
+    #[serde(rename = "inhg")]
+    InchOfMercury,
+}
+
+impl PressureUnit {
+    /// Converts a pressure in this unit to pascal.
+    pub fn to_canonical(self, value: f64) -> f64
+    {
+        match self {
+            PressureUnit::Hectopascal => value * 100.0,
+            PressureUnit::Pascal => value,
+            PressureUnit::InchOfMercury => value * 3386.389,
+        }
+    }
+
+    /// Returns the name of the unit as stored in the database.
+    pub fn as_str(self) -> &'static str
+    {
+        match self {
+            PressureUnit::Hectopascal => "hpa",
+            PressureUnit::Pascal => "pa",
+            PressureUnit::InchOfMercury => "inhg",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64)
+    {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn temperature_to_celsius()
+    {
+        assert_close(TemperatureUnit::Celsius.to_canonical(21.5), 21.5);
+        assert_close(TemperatureUnit::Fahrenheit.to_canonical(212.0), 100.0);
+        assert_close(TemperatureUnit::Fahrenheit.to_canonical(-40.0), -40.0);
+        assert_close(TemperatureUnit::Kelvin.to_canonical(273.15), 0.0);
+    }
+
+    #[test]
+    fn humidity_to_percent()
+    {
+        assert_close(HumidityUnit::Percent.to_canonical(45.0), 45.0);
+        assert_close(HumidityUnit::Fraction.to_canonical(0.45), 45.0);
+    }
+
+    #[test]
+    fn pressure_to_pascal()
+    {
+        assert_close(PressureUnit::Hectopascal.to_canonical(1013.25), 101_325.0);
+        assert_close(PressureUnit::Pascal.to_canonical(101_325.0), 101_325.0);
+        assert_close(PressureUnit::InchOfMercury.to_canonical(29.92), 101_320.758_88);
+    }
+
+    #[test]
+    fn units_deserialize_from_names()
+    {
+        assert_eq!(serde_json::from_str::<TemperatureUnit>("\"fahrenheit\"").unwrap(), TemperatureUnit::Fahrenheit);
+        assert_eq!(serde_json::from_str::<HumidityUnit>("\"fraction\"").unwrap(), HumidityUnit::Fraction);
+        assert_eq!(serde_json::from_str::<PressureUnit>("\"inhg\"").unwrap(), PressureUnit::InchOfMercury);
+        assert_eq!(PressureUnit::InchOfMercury.as_str(), "inhg");
+    }
+}